@@ -1,12 +1,131 @@
 #version 450
 
-layout(binding = 1) uniform sampler2D texSampler;
+#define MAX_POINT_LIGHTS 8
+
+struct PointLight {
+    vec4 position; // w = range
+    vec4 color;    // rgb premultiplied by intensity
+};
+
+layout(set = 0, binding = 1) uniform LightsUniform {
+    vec4 ambient;
+    vec4 directionalDirection; // w = enabled
+    vec4 directionalColor;
+    vec4 cameraPosition;
+    PointLight pointLights[MAX_POINT_LIGHTS];
+    uvec4 pointLightCount;
+} lights;
+
+layout(set = 1, binding = 0) uniform sampler2D texSampler;
 
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+layout(location = 4) in vec4 fragTangent;
 
 layout(location = 0) out vec4 outColor;
 
+const float SHININESS = 32.0;
+const float SPECULAR_STRENGTH = 0.5;
+
+// Mirrors `OutputEncoding` in output.rs
+const int ENCODING_HARDWARE = 0; // *_SRGB formats, encoded when stored
+const int ENCODING_SRGB = 1;
+const int ENCODING_SCRGB = 2;
+const int ENCODING_PQ = 3;
+
+layout(constant_id = 0) const int OUTPUT_ENCODING = ENCODING_HARDWARE;
+layout(constant_id = 1) const float PAPER_WHITE_NITS = 203.0;
+layout(constant_id = 2) const float PEAK_NITS = 1000.0;
+
+const float SCRGB_WHITE_NITS = 80.0;
+
+// Columns are the Rec. 2020 coordinates of the Rec. 709 primaries
+const mat3 REC709_TO_REC2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+vec3 blinnPhong(vec3 normal, vec3 viewDir, vec3 lightDir, vec3 lightColor, vec3 albedo) {
+    vec3 halfway = normalize(lightDir + viewDir);
+
+    float diffuse = max(dot(normal, lightDir), 0.0);
+    float specular = diffuse > 0.0
+        ? pow(max(dot(normal, halfway), 0.0), SHININESS) * SPECULAR_STRENGTH
+        : 0.0;
+
+    return (albedo * diffuse + vec3(specular)) * lightColor;
+}
+
+// Identity up to paper white (1.0), then rolls off towards the peak luminance
+vec3 tonemap(vec3 color) {
+    float peak = PEAK_NITS / PAPER_WHITE_NITS;
+    vec3 shoulder = 1.0 + (peak - 1.0) * (1.0 - exp(-(color - 1.0) / (peak - 1.0)));
+
+    return mix(color, shoulder, greaterThan(color, vec3(1.0)));
+}
+
+vec3 encodeSrgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+// SMPTE ST 2084 inverse EOTF
+vec3 encodePq(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+vec3 encodeOutput(vec3 color) {
+    color = max(color, vec3(0.0));
+
+    switch (OUTPUT_ENCODING) {
+    case ENCODING_SRGB:
+        return encodeSrgb(min(color, vec3(1.0)));
+    case ENCODING_SCRGB:
+        return tonemap(color) * PAPER_WHITE_NITS / SCRGB_WHITE_NITS;
+    case ENCODING_PQ:
+        return encodePq(REC709_TO_REC2020 * tonemap(color) * PAPER_WHITE_NITS);
+    default:
+        return color;
+    }
+}
+
 void main() {
-    outColor = texture(texSampler, fragTexCoord);
+    vec4 base = texture(texSampler, fragTexCoord) * vec4(fragColor, 1.0);
+    vec3 albedo = base.rgb;
+
+    vec3 normal = normalize(fragNormal);
+    vec3 viewDir = normalize(lights.cameraPosition.xyz - fragPosition);
+
+    vec3 color = lights.ambient.rgb * albedo;
+
+    if (lights.directionalDirection.w > 0.0) {
+        vec3 lightDir = normalize(-lights.directionalDirection.xyz);
+        color += blinnPhong(normal, viewDir, lightDir, lights.directionalColor.rgb, albedo);
+    }
+
+    for (uint i = 0; i < min(lights.pointLightCount.x, MAX_POINT_LIGHTS); i++) {
+        PointLight light = lights.pointLights[i];
+
+        vec3 toLight = light.position.xyz - fragPosition;
+        float distance = length(toLight);
+        float range = max(light.position.w, 0.0001);
+        float falloff = clamp(1.0 - distance / range, 0.0, 1.0);
+
+        color += blinnPhong(normal, viewDir, toLight / max(distance, 0.0001), light.color.rgb, albedo) * falloff * falloff;
+    }
+
+    outColor = vec4(encodeOutput(color), base.a);
 }
\ No newline at end of file
@@ -1,20 +1,34 @@
 #version 450
 
-layout(binding = 0) uniform UniformBufferObject {
-    mat4 model;
+layout(set = 0, binding = 0) uniform UniformBufferObject {
     mat4 view;
     mat4 proj;
 } ubo;
 
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+} pcs;
+
 layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inColor;
 layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec3 inNormal;
+layout(location = 4) in vec4 inTangent;
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec3 fragPosition;
+layout(location = 3) out vec3 fragNormal;
+layout(location = 4) out vec4 fragTangent;
 
 void main() {
+    vec4 worldPosition = pcs.model * vec4(inPosition, 1.0);
+    mat3 normalMatrix = mat3(transpose(inverse(pcs.model)));
+
     fragColor = inColor;
     fragTexCoord = inTexCoord;
-    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition, 1.0);
+    fragPosition = worldPosition.xyz;
+    fragNormal = normalMatrix * inNormal;
+    fragTangent = vec4(mat3(pcs.model) * inTangent.xyz, inTangent.w);
+    gl_Position = ubo.proj * ubo.view * worldPosition;
 }
\ No newline at end of file
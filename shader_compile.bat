@@ -2,4 +2,6 @@ C:/VulkanSDK/1.3.275.0/Bin/glslc.exe -fshader-stage=vertex assets/shaders/vertex
 
 C:/VulkanSDK/1.3.275.0/Bin/glslc.exe -fshader-stage=fragment assets/shaders/fragment.glsl -o assets/shaders/compiled/fragment.spv
 
+C:/VulkanSDK/1.3.275.0/Bin/glslc.exe -fshader-stage=compute assets/shaders/compute.glsl -o assets/shaders/compiled/compute.spv
+
 PAUSE
\ No newline at end of file
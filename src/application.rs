@@ -1,17 +1,23 @@
 //TODO:
-//  - Create another queue dedicated for transfer operations,
-//  - As said remove the mip generation outside the code,
-//  - Support for different mssa sample counts,
 //  ...
     
-use crate::{camera::Camera, input::Input, MyError};
+use crate::{
+    camera::Camera,
+    config::{AppConfig, DeviceSelector, OutputFormat, PresentMode},
+    input::Input,
+    scene::{
+        ImportedModel, MeshId, MeshInstance, Scene, TextureId,
+        DEFAULT_MESH, DEFAULT_TEXTURE, MAX_POINT_LIGHTS
+    },
+    MyError
+};
 
 use nalgebra_glm as glm;
 use std::{
-    collections::{HashMap, HashSet}, ffi::CStr, fs::File, hash::{
+    collections::{HashMap, HashSet}, ffi::CStr, hash::{
         Hash,
         Hasher,
-    }, io::BufReader, mem::size_of, os::raw::c_void, ptr::copy_nonoverlapping as memcpy, time::Instant
+    }, mem::size_of, os::raw::c_void, path::{Path, PathBuf}, ptr::copy_nonoverlapping as memcpy, time::Instant
 };
 use sllog::{error, info, trace, warn};
 use vmm::{vec2, vec3, Identity, MatTransforms};
@@ -28,6 +34,34 @@ use vulkanalia::{
 // WINIT
 use winit::window::Window;
 
+mod allocator;
+mod barrier;
+mod compute;
+mod features;
+mod frame;
+mod gltf_loader;
+mod output;
+mod pipeline_cache;
+mod render_graph;
+mod screenshot;
+mod shaders;
+mod texture_loader;
+mod transfer;
+mod uniform_ring;
+
+use allocator::{Allocation, Allocator};
+use barrier::ImageTransition;
+pub use allocator::MemoryStats;
+pub use features::EnabledFeatures;
+pub use frame::Frame;
+use output::{OutputConstants, OutputEncoding};
+use render_graph::{Load, PassDesc, PassId, RenderGraph};
+use screenshot::{get_screenshot_name, Screenshot};
+use shaders::{ShaderStage, ShaderWatcher};
+use texture_loader::TextureLevels;
+use transfer::{BufferUsage, ImageLevels, UploadTarget};
+use uniform_ring::{RingSlice, UniformRing};
+
 // CONSTANTS
 const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 216);
 const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
@@ -36,6 +70,10 @@ const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[
     vk::KHR_SWAPCHAIN_EXTENSION.name
 ];
 const MAX_FRAMES_IN_FLIGHT: usize = 2;
+const MAX_TEXTURES: u32 = 256;
+const VERTEX_SHADER: &str = "vertex.glsl";
+const FRAGMENT_SHADER: &str = "fragment.glsl";
+const COMPUTE_SHADER: &str = "compute.glsl";
 
 // STRUCTS
 #[repr(C)]
@@ -44,10 +82,19 @@ struct Vertex {
     position: glm::Vec3,
     color: glm::Vec3,
     tex_coord: glm::Vec2,
+    normal: glm::Vec3,
+    /// `w` holds the bitangent sign.
+    tangent: glm::Vec4,
 }
 impl Vertex {
-    const fn new(position: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2) -> Self {
-        Self { position, color, tex_coord }
+    const fn new(position: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2, normal: glm::Vec3) -> Self {
+        Self {
+            position,
+            color,
+            tex_coord,
+            normal,
+            tangent: glm::Vec4::new(1.0, 0.0, 0.0, 1.0),
+        }
     }
     
     fn binding_description() -> vk::VertexInputBindingDescription {
@@ -58,7 +105,7 @@ impl Vertex {
             .build()
     }
     
-    fn attribute_descritptions() -> [vk::VertexInputAttributeDescription; 3] {
+    fn attribute_descritptions() -> [vk::VertexInputAttributeDescription; 5] {
         let position = vk::VertexInputAttributeDescription::builder()
             .binding(0)
             .location(0)
@@ -80,19 +127,35 @@ impl Vertex {
             .offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>()) as u32)
             .build();
         
-        [position, color, tex_coord]
+        let normal = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(3)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>() + size_of::<glm::Vec2>()) as u32)
+            .build();
+        
+        let tangent = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(4)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((size_of::<glm::Vec3>() * 3 + size_of::<glm::Vec2>()) as u32)
+            .build();
+        
+        [position, color, tex_coord, normal, tangent]
     }
     
     fn get_default_rectangle() -> [Self; 8] {
+        let normal = glm::vec3(0.0, 0.0, 1.0);
+
         [
-            Vertex::new(glm::vec3(-0.5, -0.5, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec2(1.0, 0.0)),
-            Vertex::new(glm::vec3(0.5, -0.5, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec2(0.0, 0.0)),
-            Vertex::new(glm::vec3(0.5, 0.5, 0.0), glm::vec3(0.0, 0.0, 1.0), glm::vec2(0.0, 1.0)),
-            Vertex::new(glm::vec3(-0.5, 0.5, 0.0), glm::vec3(1.0, 1.0, 1.0), glm::vec2(1.0, 1.0)),
-            Vertex::new(glm::vec3(-0.5, -0.5, -0.5), glm::vec3(1.0, 0.0, 0.0), glm::vec2(1.0, 0.0)),
-            Vertex::new(glm::vec3(0.5, -0.5, -0.5), glm::vec3(0.0, 1.0, 0.0), glm::vec2(0.0, 0.0)),
-            Vertex::new(glm::vec3(0.5, 0.5, -0.5), glm::vec3(0.0, 0.0, 1.0), glm::vec2(0.0, 1.0)),
-            Vertex::new(glm::vec3(-0.5, 0.5, -0.5), glm::vec3(1.0, 1.0, 1.0), glm::vec2(1.0, 1.0)) 
+            Vertex::new(glm::vec3(-0.5, -0.5, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec2(1.0, 0.0), normal),
+            Vertex::new(glm::vec3(0.5, -0.5, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec2(0.0, 0.0), normal),
+            Vertex::new(glm::vec3(0.5, 0.5, 0.0), glm::vec3(0.0, 0.0, 1.0), glm::vec2(0.0, 1.0), normal),
+            Vertex::new(glm::vec3(-0.5, 0.5, 0.0), glm::vec3(1.0, 1.0, 1.0), glm::vec2(1.0, 1.0), normal),
+            Vertex::new(glm::vec3(-0.5, -0.5, -0.5), glm::vec3(1.0, 0.0, 0.0), glm::vec2(1.0, 0.0), normal),
+            Vertex::new(glm::vec3(0.5, -0.5, -0.5), glm::vec3(0.0, 1.0, 0.0), glm::vec2(0.0, 0.0), normal),
+            Vertex::new(glm::vec3(0.5, 0.5, -0.5), glm::vec3(0.0, 0.0, 1.0), glm::vec2(0.0, 1.0), normal),
+            Vertex::new(glm::vec3(-0.5, 0.5, -0.5), glm::vec3(1.0, 1.0, 1.0), glm::vec2(1.0, 1.0), normal) 
         ]
     }
 }
@@ -101,6 +164,8 @@ impl PartialEq for Vertex {
         self.position == other.position
             && self.color == other.color
             && self.tex_coord == other.tex_coord
+            && self.normal == other.normal
+            && self.tangent == other.tangent
     }
 }
 
@@ -116,68 +181,171 @@ impl Hash for Vertex {
         self.color[2].to_bits().hash(state);
         self.tex_coord[0].to_bits().hash(state);
         self.tex_coord[1].to_bits().hash(state);
+        self.normal[0].to_bits().hash(state);
+        self.normal[1].to_bits().hash(state);
+        self.normal[2].to_bits().hash(state);
+        self.tangent[0].to_bits().hash(state);
+        self.tangent[1].to_bits().hash(state);
+        self.tangent[2].to_bits().hash(state);
+        self.tangent[3].to_bits().hash(state);
     }
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct UniformBufferObject {
-    model: glm::Mat4,
     view: glm::Mat4,
     proj: glm::Mat4,
 }
 
+/// std140 mirror of `LightsUniform` in `fragment.glsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct LightsUniform {
+    ambient: glm::Vec4,
+    /// `w` is 1.0 when the directional light is enabled.
+    directional_direction: glm::Vec4,
+    directional_color: glm::Vec4,
+    camera_position: glm::Vec4,
+    point_lights: [PointLightUniform; MAX_POINT_LIGHTS],
+    point_light_count: glm::UVec4,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct PointLightUniform {
+    /// `w` holds the range.
+    position: glm::Vec4,
+    color: glm::Vec4,
+}
+
+/// Slices of the [`UniformRing`] reserved for a frame's descriptor set.
+#[derive(Clone, Copy, Debug)]
+struct FrameUniforms {
+    camera: RingSlice<UniformBufferObject>,
+    lights: RingSlice<LightsUniform>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Mesh {
+    vertex_buffer: vk::Buffer,
+    vertex_buffer_memory: Allocation,
+    index_buffer: vk::Buffer,
+    index_buffer_memory: Allocation,
+    sub_meshes: Vec<SubMesh>,
+}
+
+/// Public call that created meshes or textures, see [`App::recover`].
+#[derive(Clone, Debug)]
+enum ResourceCommand {
+    AddMesh(PathBuf),
+    ReplaceMesh(MeshId, PathBuf),
+    AddDefaultRectangle,
+    AddTexture(PathBuf),
+    LoadGltf(PathBuf),
+    CreateComputeTarget(u32, u32),
+}
+
+/// Index range of a [`Mesh`] drawn with a single texture.
+#[derive(Clone, Copy, Debug, Default)]
+struct SubMesh {
+    first_index: u32,
+    index_count: u32,
+    /// Falls back to the instance texture, then [`DEFAULT_TEXTURE`], when `None`.
+    texture: Option<TextureId>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Texture {
+    image: vk::Image,
+    image_memory: Allocation,
+    image_view: vk::ImageView,
+    mip_levels: u32,
+    width: u32,
+    height: u32,
+    descriptor_set: vk::DescriptorSet,
+}
+
 #[derive(Clone, Debug, Default)]
 struct AppData {
     messenger: vk::DebugUtilsMessengerEXT,
     physical_device: vk::PhysicalDevice,
+    features: EnabledFeatures,
+    allocator: Allocator,
     msaa_samples: vk::SampleCountFlags,
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
+    compute_queue: vk::Queue,
+    transfer_queue: vk::Queue,
     surface: vk::SurfaceKHR,
     swapchain: vk::SwapchainKHR,
     swapchain_format: vk::Format,
+    output_format: OutputFormat,
+    output_encoding: OutputEncoding,
+    /// Whether `VK_EXT_swapchain_colorspace` is enabled, needed for HDR outputs.
+    colorspace_extension: bool,
     swapchain_extent: vk::Extent2D,
     swapchain_images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
+    swapchain_usage: vk::ImageUsageFlags,
+    render_graph: RenderGraph,
+    /// Pass drawing the scene, `render_pass` is its render pass.
+    scene_pass: PassId,
     render_pass: vk::RenderPass,
     descriptor_set_layout: vk::DescriptorSetLayout,
+    texture_descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_cache: vk::PipelineCache,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
-    framebuffers: Vec<vk::Framebuffer>,
+    /// SPIR-V used whenever the pipeline is (re)created.
+    vertex_shader: Vec<u8>,
+    fragment_shader: Vec<u8>,
+    compute_shader: Vec<u8>,
+    compute_descriptor_set_layout: vk::DescriptorSetLayout,
+    compute_descriptor_pool: vk::DescriptorPool,
+    compute_pipeline_layout: vk::PipelineLayout,
+    compute_pipeline: vk::Pipeline,
+    compute_command_pool: vk::CommandPool,
+    /// Storage image descriptor set of each compute target texture.
+    compute_targets: HashMap<TextureId, vk::DescriptorSet>,
+    transfer_command_pool: vk::CommandPool,
+    pending_uploads: Vec<transfer::PendingUpload>,
+    /// Graphics queue submissions acquiring finished uploads.
+    acquire_submissions: Vec<(vk::CommandBuffer, vk::Fence)>,
     command_pool: vk::CommandPool,
-    command_buffers: Vec<vk::CommandBuffer>,
+    /// One pool per frame in flight, reset before its frame is recorded again.
+    frame_command_pools: Vec<vk::CommandPool>,
+    frame_command_buffers: Vec<vk::CommandBuffer>,
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
     in_flight_fences: Vec<vk::Fence>,
     images_in_flight: Vec<vk::Fence>,
-    vertex_buffer: vk::Buffer,
-    vertex_buffer_memory: vk::DeviceMemory,
-    index_buffer: vk::Buffer,
-    index_buffer_memory: vk::DeviceMemory,
-    uniform_buffers: Vec<vk::Buffer>,
-    uniform_buffers_memory: Vec<vk::DeviceMemory>,
+    meshes: Vec<Mesh>,
+    uniform_ring: UniformRing,
+    /// Uniforms bound by `descriptor_sets`, one per swapchain image.
+    frame_uniforms: Vec<FrameUniforms>,
     descriptor_pool: vk::DescriptorPool,
     descriptor_sets: Vec<vk::DescriptorSet>,
-    mip_levels: u32,
-    color_image: vk::Image,
-    color_image_memory: vk::DeviceMemory,
-    color_image_view: vk::ImageView,
-    texture_image: vk::Image,
-    texture_image_memory: vk::DeviceMemory,
-    texture_image_view: vk::ImageView,
+    texture_descriptor_pool: vk::DescriptorPool,
+    textures: Vec<Texture>,
+    white_texture: Option<TextureId>,
+    /// Diffuse textures of OBJ meshes by path, shared by every mesh using them so
+    /// replacing a mesh doesn't upload them again.
+    obj_textures: HashMap<PathBuf, TextureId>,
     texture_sampler: vk::Sampler,
-    depth_image: vk::Image,
-    depth_image_memory: vk::DeviceMemory,
-    depth_image_view: vk::ImageView,
-    vertices: Vec<Vertex>,
-    indices: Vec<u32>,
+    scene: Scene,
+    present_mode: PresentMode,
+    headless: bool,
+    offscreen_image: vk::Image,
+    offscreen_image_memory: Allocation,
 }
 
 #[derive(Debug, Clone, Copy)]
 struct QueueFamilyIndices {
     graphics: u32,
     present: u32,
+    compute: u32,
+    transfer: u32,
 }
 impl QueueFamilyIndices {
     unsafe fn get(
@@ -196,20 +364,49 @@ impl QueueFamilyIndices {
             )
             .map(|i| i as u32);
 
-        let present = properties
+        // Without a surface nothing is presented, so the graphics queue is reused
+        let present = if data.headless {
+            graphics
+        }
+        else {
+            let supports_present = |i: u32| instance
+                .get_physical_device_surface_support_khr(physical_device, i, data.surface)
+                .unwrap_or(false);
+
+            // Presenting from the graphics family needs no ownership transfers
+            graphics
+                .filter(|g| supports_present(*g))
+                .or_else(|| (0..properties.len() as u32).find(|i| supports_present(*i)))
+        };
+
+        // Prefer the graphics family so compute results need no sharing between families
+        let compute = graphics
+            .filter(|g| properties[*g as usize].queue_flags.contains(vk::QueueFlags::COMPUTE))
+            .or_else(|| properties
+                .iter()
+                .position(|p| p.queue_flags.contains(vk::QueueFlags::COMPUTE))
+                .map(|i| i as u32)
+            );
+
+        // A family without graphics or compute is usually backed by a dedicated DMA engine
+        let transfer = properties
             .iter()
-            .enumerate()
-            .position(|(i, _)| 
-                instance.get_physical_device_surface_support_khr(
-                    physical_device, 
-                    i as u32,
-                    data.surface
-                ).is_ok()
+            .position(|p| 
+                p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !p.queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
             )
-            .map(|i| i as u32);
+            .or_else(|| properties
+                .iter()
+                .position(|p| 
+                    p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                        && !p.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+                )
+            )
+            .map(|i| i as u32)
+            .or(graphics);
 
-        if let (Some(graphics), Some(present)) = (graphics, present) {
-            Ok(Self { graphics, present })
+        if let (Some(graphics), Some(present), Some(compute), Some(transfer)) = (graphics, present, compute, transfer) {
+            Ok(Self { graphics, present, compute, transfer })
         }
         else {
             Err("Missing required queue families!".into())
@@ -261,63 +458,80 @@ pub struct App {
     start: Instant,
     camera: Camera,
     pub input: Input,
+    config: AppConfig,
+    shader_watcher: Option<ShaderWatcher>,
+    /// Where the next rendered frame is saved.
+    screenshot_path: Option<PathBuf>,
+    /// Replayed in order to reload the meshes and textures after a device loss.
+    resources: Vec<ResourceCommand>,
+    destroyed: bool,
 }
 impl App {
     // PUBLIC
-    pub unsafe fn create(window: &Window) -> Result<Self, MyError> {
-        let loader = LibloadingLoader::new(LIBRARY)?;
-        let entry = Entry::new(loader).map_err(|b| error!("{}", b)).unwrap();
-        let mut data = AppData::default();
-        let instance = create_instance(window, &entry, &mut data)?;
+    pub unsafe fn create(window: &Window, config: &AppConfig) -> Result<Self, MyError> {
+        let loader = LibloadingLoader::new(LIBRARY).map_err(|e| MyError::Other(Box::new(e)))?;
+        let entry = Entry::new(loader)?;
+        let mut data = AppData {
+            present_mode: config.present_mode,
+            output_format: config.output_format,
+            ..Default::default()
+        };
+        let instance = create_instance(Some(window), &entry, &mut data)?;
         data.surface = vk_window::create_surface(&instance, &window, &window)?;
-        pick_physical_device(&instance, &mut data)?;
+        pick_physical_device(&instance, &mut data, config.get_device().as_ref())?;
         let device = create_logical_device(&entry, &instance, &mut data)?;
+        data.allocator = Allocator::new(&instance, data.physical_device);
         create_swapchain(window, &instance, &device, &mut data)?;
         create_swapchain_image_views(&device, &mut data)?;
-        create_render_pass(&instance, &device, &mut data)?;
-        create_descriptor_set_layout(&device, &mut data)?;
-        create_pipeline(&device, &mut data)?;
-        create_command_pool(&instance, &device, &mut data)?;
-        create_color_objects(&instance, &device, &mut data)?;
-        create_depth_objects(&instance, &device, &mut data)?;
-        create_framebuffers(&device, &mut data)?;
-        create_texture_image(&instance, &device, &mut data)?;
-        create_texture_image_view(&device, &mut data)?;
-        create_texture_sampler(&device, &mut data)?;
-        load_model(&mut data)?;
-        create_vertex_buffer(&instance, &device, &mut data)?;
-        create_index_buffer(&instance, &device, &mut data)?;
-        create_uniform_buffers(&instance, &device, &mut data)?;
-        create_descriptor_pool(&device, &mut data)?;
-        create_descriptor_sets(&device, &mut data)?;
-        create_command_buffers(&device, &mut data)?;
-        create_sync_objects(&device, &mut data)?;
-        
-        let camera = Camera::new(
-            vmm::to_radians(45.0) as f32, 
-            data.swapchain_extent.width as f32, 
-            data.swapchain_extent.height as f32, 
-            0.1, 
-            100.0
-        );
         
-        let input = Input::new();
+        Self::build(entry, instance, device, data, config)
+    }
+    
+    /// Creates an [`App`] without a window or surface, rendering into an offscreen
+    /// color image of the given size instead of a swapchain.
+    pub unsafe fn create_headless(width: u32, height: u32, config: &AppConfig) -> Result<Self, MyError> {
+        let loader = LibloadingLoader::new(LIBRARY).map_err(|e| MyError::Other(Box::new(e)))?;
+        let entry = Entry::new(loader)?;
+        let mut data = AppData {
+            headless: true,
+            present_mode: config.present_mode,
+            output_format: config.output_format,
+            ..Default::default()
+        };
+        let instance = create_instance(None, &entry, &mut data)?;
+        pick_physical_device(&instance, &mut data, config.get_device().as_ref())?;
+        let device = create_logical_device(&entry, &instance, &mut data)?;
+        data.allocator = Allocator::new(&instance, data.physical_device);
+        create_offscreen_target(&device, &mut data, width, height)?;
+        create_swapchain_image_views(&device, &mut data)?;
 
-        Ok(Self {
-            entry,
-            instance,
-            data,
-            device,
-            frame: 0,
-            resized: false,
-            start: Instant::now(),
-            camera,
-            input,
-        })
+        Self::build(entry, instance, device, data, config)
     }
     
     pub unsafe fn render(&mut self, window: &Window) -> Result<(), MyError> {
+        self.render_with(window, |_| Ok(()))
+    }
+
+    /// Renders the scene, then lets `draw` record more commands into the frame.
+    /// The command buffer is recorded from scratch every frame.
+    pub unsafe fn render_with<F>(&mut self, window: &Window, draw: F) -> Result<(), MyError>
+    where
+        F: FnOnce(&mut Frame) -> Result<(), MyError>,
+    {
         self.camera.on_update(&self.input);
+        self.reload_shaders_if_changed();
+
+        if self.config.screenshot_key.is_some_and(|k| self.input.take_key_press(k)) {
+            self.capture_screenshot(get_screenshot_name());
+        }
+
+        // A screenshot the swapchain can't provide is dropped, the frame still renders
+        if self.screenshot_path.is_some() && !Screenshot::is_supported(&self.data) {
+            self.screenshot_path = None;
+            warn!("The surface doesn't support copying from swapchain images, skipping the screenshot");
+        }
+
+        self.poll_uploads()?;
         let in_flight_fence = self.data.in_flight_fences[self.frame];
 
         self.device.wait_for_fences(&[in_flight_fence], true, u64::MAX)?;
@@ -344,11 +558,18 @@ impl App {
 
         self.data.images_in_flight[image_index] = in_flight_fence;
 
+        let screenshot = self.screenshot_path.take().and_then(|path| {
+            Screenshot::create(&self.device, &mut self.data, path)
+                .map_err(|e| error!("Failed to capture screenshot: {}", e))
+                .ok()
+        });
+
         self.update_uniform_buffer(image_index)?;
+        let command_buffer = self.record_frame(image_index, screenshot.as_ref(), draw)?;
 
         let wait_semaphores = &[self.data.image_available_semaphores[self.frame]];
         let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let command_buffers = &[self.data.command_buffers[image_index]];
+        let command_buffers = &[command_buffer];
         let signal_semaphores = &[self.data.render_finished_semaphores[self.frame]];
         let submit_info = vk::SubmitInfo::builder()
             .wait_semaphores(wait_semaphores)
@@ -361,6 +582,17 @@ impl App {
         self.device
             .queue_submit(self.data.graphics_queue, &[submit_info], in_flight_fence)?;
 
+        if let Some(screenshot) = screenshot {
+            let saved = self.device
+                .wait_for_fences(&[in_flight_fence], true, u64::MAX)
+                .map_err(MyError::from)
+                .and_then(|_| screenshot.save(&self.device, &mut self.data));
+
+            if let Err(e) = saved {
+                error!("Failed to save screenshot: {}", e);
+            }
+        }
+
         let swapchains = &[self.data.swapchain];
         let image_indices = &[image_index as u32];
         let present_info = vk::PresentInfoKHR::builder()
@@ -385,20 +617,39 @@ impl App {
         Ok(())
     }
     
+    /// Destroys every Vulkan object, later calls do nothing.
     #[rustfmt::skip]
     pub unsafe fn destroy(&mut self) {
-        self.device.device_wait_idle().unwrap();
+        if self.destroyed {
+            return;
+        }
+        self.destroyed = true;
+
+        // A lost device never becomes idle, but its objects can still be destroyed
+        if let Err(e) = self.device.device_wait_idle() {
+            warn!("Destroying the app without waiting for the device: {}", e);
+        }
+
+        transfer::destroy_uploads(&self.device, &mut self.data);
 
         self.destroy_swapchain();
         self.device.destroy_sampler(self.data.texture_sampler, None);
-        self.device.destroy_image_view(self.data.texture_image_view, None);
-        self.device.destroy_image(self.data.texture_image, None);
-        self.device.free_memory(self.data.texture_image_memory, None);
+        self.data.textures.iter().for_each(|t| destroy_texture(&self.device, &mut self.data.allocator, t));
+        self.device.destroy_descriptor_pool(self.data.texture_descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.data.texture_descriptor_set_layout, None);
         self.device.destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
-        self.device.destroy_buffer(self.data.vertex_buffer, None);
-        self.device.free_memory(self.data.vertex_buffer_memory, None);
-        self.device.destroy_buffer(self.data.index_buffer, None);
-        self.device.free_memory(self.data.index_buffer_memory, None);
+        self.device.destroy_pipeline(self.data.compute_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.compute_pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.data.compute_descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.data.compute_descriptor_set_layout, None);
+
+        if let Some(path) = &self.config.pipeline_cache_path {
+            if let Err(e) = pipeline_cache::save_pipeline_cache(&self.device, &self.data, path) {
+                warn!("Failed to save the pipeline cache: {}", e);
+            }
+        }
+        self.device.destroy_pipeline_cache(self.data.pipeline_cache, None);
+        self.data.meshes.iter().for_each(|m| destroy_mesh(&self.device, &mut self.data.allocator, m));
 
         for i in 0..MAX_FRAMES_IN_FLIGHT {
             self.device.destroy_fence(self.data.in_flight_fences[i], None);
@@ -413,9 +664,16 @@ impl App {
             );
         }
 
+        self.data.frame_command_pools.iter().for_each(|p| self.device.destroy_command_pool(*p, None));
         self.device.destroy_command_pool(self.data.command_pool, None);
+        self.device.destroy_command_pool(self.data.compute_command_pool, None);
+        self.device.destroy_command_pool(self.data.transfer_command_pool, None);
+        self.data.allocator.destroy(&self.device);
         self.device.destroy_device(None);
-        self.instance.destroy_surface_khr(self.data.surface, None);
+
+        if !self.data.headless {
+            self.instance.destroy_surface_khr(self.data.surface, None);
+        }
 
         if VALIDATION_ENABLED {
             self.instance.destroy_debug_utils_messenger_ext(self.data.messenger, None);
@@ -424,239 +682,845 @@ impl App {
         self.instance.destroy_instance(None);
     }
 
-    // Callbacks
-    pub fn mouse_scrolled_callback(&mut self, x: f32, y: f32) {
-        self.camera.mouse_scrolled_callback(x, y);
+    /// Replaces the default mesh with the OBJ at `path`, resolved against the
+    /// configured asset root.
+    pub unsafe fn load_mesh(&mut self, path: impl AsRef<Path>) -> Result<(), MyError> {
+        self.replace_mesh(DEFAULT_MESH, path)
     }
 
-    // PRIVATE
-    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<(), MyError>
-    {
-        let time = self.start.elapsed().as_secs_f32();
+    /// Uploads the OBJ at `path` as a new mesh that scene instances can reference.
+    pub unsafe fn add_mesh(&mut self, path: impl AsRef<Path>) -> Result<MeshId, MyError> {
+        let id = self.add_mesh_async(path)?;
+        self.wait_for_uploads()?;
 
-        let model = glm::rotate(
-            &glm::Mat4::identity(), 
-            vmm::to_degrees(90.0) as f32, 
-            &glm::vec3(0.0, 1.0, 1.0)
-        );
+        Ok(id)
+    }
 
-        let view = self.camera.get_view_matrix();
+    /// Like [`App::add_mesh`], but returns as soon as the upload is submitted to the
+    /// transfer queue. Instances of the mesh are drawn once it finished.
+    pub unsafe fn add_mesh_async(&mut self, path: impl AsRef<Path>) -> Result<MeshId, MyError> {
+        let path = self.config.resolve_asset(path.as_ref())?;
+        
+        let mesh = create_obj_mesh(&self.instance, &self.device, &mut self.data, &path)?;
+        self.data.meshes.push(mesh);
+        self.resources.push(ResourceCommand::AddMesh(path));
 
-        let proj = self.camera.get_projection_matrix();
+        Ok(self.data.meshes.len() - 1)
+    }
 
-        let ubo = UniformBufferObject { model, view: view.clone(), proj };
+    /// Uploads the two colored quads of the default rectangle as a new mesh, a
+    /// reference scene that needs no assets.
+    pub unsafe fn add_default_rectangle(&mut self) -> Result<MeshId, MyError> {
+        let indices = [0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
 
-        // Copy
+        let mesh = create_mesh(&self.instance, &self.device, &mut self.data, &Vertex::get_default_rectangle(), &indices)?;
+        self.data.meshes.push(mesh);
+        self.resources.push(ResourceCommand::AddDefaultRectangle);
+        self.wait_for_uploads()?;
 
-        let memory = self.device.map_memory(
-            self.data.uniform_buffers_memory[image_index],
-            0,
-            size_of::<UniformBufferObject>() as u64,
-            vk::MemoryMapFlags::empty(),
-        )?;
+        Ok(self.data.meshes.len() - 1)
+    }
 
-        memcpy(&ubo, memory.cast(), 1);
+    /// Replaces the mesh `id` with the OBJ at `path`, freeing the old buffers. Diffuse
+    /// textures already uploaded for another OBJ are reused.
+    pub unsafe fn replace_mesh(&mut self, id: MeshId, path: impl AsRef<Path>) -> Result<(), MyError> {
+        if id >= self.data.meshes.len() {
+            return Err(format!("Unknown mesh id {}!", id).into());
+        }
+
+        let path = self.config.resolve_asset(path.as_ref())?;
+        let mesh = create_obj_mesh(&self.instance, &self.device, &mut self.data, &path)?;
 
-        self.device.unmap_memory(self.data.uniform_buffers_memory[image_index]);
+        // The old buffers may still be waiting for their acquire
+        self.wait_for_uploads()?;
+        self.device.device_wait_idle()?;
+        destroy_mesh(&self.device, &mut self.data.allocator, &self.data.meshes[id]);
+        self.data.meshes[id] = mesh;
+        self.resources.push(ResourceCommand::ReplaceMesh(id, path));
 
         Ok(())
     }
 
-    #[rustfmt::skip]
-    unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<(), MyError> {
-        self.device.device_wait_idle()?;
-        self.destroy_swapchain();
-        create_swapchain(window, &self.instance, &self.device, &mut self.data)?;
-        create_swapchain_image_views(&self.device, &mut self.data)?;
-        create_render_pass(&self.instance, &self.device, &mut self.data)?;
-        create_pipeline(&self.device, &mut self.data)?;
-        create_color_objects(&self.instance, &self.device, &mut self.data)?;
-        create_depth_objects(&self.instance, &self.device, &mut self.data)?;
-        create_framebuffers(&self.device, &mut self.data)?;
-        create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
-        create_descriptor_pool(&self.device, &mut self.data)?;
-        create_descriptor_sets(&self.device, &mut self.data)?;
-        create_command_buffers(&self.device, &mut self.data)?;
-        self.data.images_in_flight.resize(self.data.swapchain_images.len(), vk::Fence::null());
-        Ok(()) 
-    }
-    
-    #[rustfmt::skip]
-    unsafe fn destroy_swapchain(&mut self)
-    {
-        self.device.free_command_buffers(self.data.command_pool, &self.data.command_buffers);
-        self.device.destroy_descriptor_pool(self.data.descriptor_pool, None);
-        self.data.uniform_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
-        self.data.uniform_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
-        self.device.destroy_image_view(self.data.depth_image_view, None);
-        self.device.free_memory(self.data.depth_image_memory, None);
-        self.device.destroy_image(self.data.depth_image, None);
-        self.device.destroy_image_view(self.data.color_image_view, None);
-        self.device.free_memory(self.data.color_image_memory, None);
-        self.device.destroy_image(self.data.color_image, None);
-        self.data.framebuffers.iter().for_each(|f| self.device.destroy_framebuffer(*f, None));
-        self.device.destroy_pipeline(self.data.pipeline, None);
-        self.device.destroy_pipeline_layout(self.data.pipeline_layout, None);
-        self.device.destroy_render_pass(self.data.render_pass, None);
-        self.data.swapchain_image_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
-        self.device.destroy_swapchain_khr(self.data.swapchain, None);
-    }
-}
+    /// Uploads the image at `path` as a new texture that scene instances can reference.
+    pub unsafe fn add_texture(&mut self, path: impl AsRef<Path>) -> Result<TextureId, MyError> {
+        let id = self.add_texture_async(path)?;
+        self.wait_for_uploads()?;
 
-// Helper Functions
-unsafe fn create_instance(
-        window: &Window,
-        entry: &Entry,
-        data: &mut AppData,
-) -> Result<Instance, MyError> 
-{
-    let application_info = vk::ApplicationInfo::builder()
-        .application_name(b"Vulkan Tutorial\0")
-        .application_version(vk::make_version(1, 0, 0))
-        .engine_name(b"No Engine\0")
-        .engine_version(vk::make_version(1, 0, 0))
-        .api_version(vk::make_version(1, 0, 0));
-    
-    let available_layers = entry
-        .enumerate_instance_layer_properties()?
-        .iter()
-        .map(|l| l.layer_name)
-        .collect::<HashSet<_>>();
-    
-    if VALIDATION_ENABLED && !available_layers.contains(&VALIDATION_LAYER) {
-        return Err("Validation layer requested but not supported!".into());
+        Ok(id)
     }
-    
-    let layers = if VALIDATION_ENABLED {
-        vec![VALIDATION_LAYER.as_ptr()]
+
+    /// Like [`App::add_texture`], but returns as soon as the upload is submitted to
+    /// the transfer queue. Sub-meshes using the texture are drawn once it finished.
+    pub unsafe fn add_texture_async(&mut self, path: impl AsRef<Path>) -> Result<TextureId, MyError> {
+        let path = self.config.resolve_asset(path.as_ref())?;
+        
+        let texture = create_texture(&self.instance, &self.device, &mut self.data, &path)?;
+        self.data.textures.push(texture);
+        self.resources.push(ResourceCommand::AddTexture(path));
+
+        Ok(self.data.textures.len() - 1)
     }
-    else {
-        Vec::new()
-    };
 
-    let mut extensions = vk_window::get_required_instance_extensions(window)
-        .iter()
-        .map(|e| e.as_ptr())
-        .collect::<Vec<_>>();
-    
-    if VALIDATION_ENABLED {
-        extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
+    pub fn has_pending_uploads(&self) -> bool {
+        !self.data.pending_uploads.is_empty()
     }
+    /// Blocks until every asynchronous upload finished.
+    pub unsafe fn wait_for_uploads(&mut self) -> Result<(), MyError> {
+        transfer::wait_for_uploads(&self.device, &mut self.data)?;
 
-    // Required by Vulkan SDK on macOS since 1.3.216
-    let flags = if 
-        cfg!(target_os = "macos") &&
-        entry.version()? >= PORTABILITY_MACOS_VERSION
-    {
-        info!("Enabling extensions for macOS portablilty!");
-        extensions.push(vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION.name.as_ptr());
-        extensions.push(vk::KHR_PORTABILITY_ENUMERATION_EXTENSION.name.as_ptr());
-        
-        vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
-    } 
-    else {
-        vk::InstanceCreateFlags::empty()
-    };
-    
-    let mut info = vk::InstanceCreateInfo::builder()
-        .application_info(&application_info)
-        .enabled_layer_names(&layers)
-        .enabled_extension_names(&extensions)
-        .flags(flags);
-        
-    let mut debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-        .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
-        .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
-        .user_callback(Some(debug_callback));
-    
-    if VALIDATION_ENABLED {
-        info = info.push_next(&mut debug_info);
+        Ok(())
     }
-    
-    let instance = entry.create_instance(&info, None)?;
-    Ok(instance)
-}
 
-unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> Result<(), MyError> {
-    if let Some((_, physical_device)) = instance.enumerate_physical_devices()?
-        .iter()
-        .map(|pd| 
-            (check_physical_device(
-                instance,
-                data,
-                *pd
-            ), pd)
-        )
-        .filter(|(val, _)| 
-            val.is_ok()
-        )
-        .min_by_key(|(val, _)| {
-            *val.as_ref().unwrap()
-        })
-    {
-        let properties = instance.get_physical_device_properties(*physical_device);
-        warn!("Physical Selected:\n  Name: {}\n  Type: {:?}", properties.device_name, properties.device_type);
-        data.physical_device = *physical_device;
-        data.msaa_samples = get_max_msaa_samples(instance, data);
+    /// Uploads every mesh and base color texture of the glTF 2.0 file at `path`
+    /// and adds an instance per primitive to the scene, placed by its node transform.
+    pub unsafe fn load_gltf(&mut self, path: impl AsRef<Path>) -> Result<ImportedModel, MyError> {
+        let path = self.config.resolve_asset(path.as_ref())?;
+        let document = gltf_loader::read_gltf(&path)?;
 
-        return Ok(())
-    }
+        let mut model = ImportedModel {
+            nodes: document.nodes,
+            roots: document.roots,
+            ..Default::default()
+        };
 
-    Err("Failed to find any suitable Physical Device!".into())
-}
+        // Meshes
+
+        for primitive in &document.primitives {
+            let mesh = create_mesh(
+                &self.instance,
+                &self.device,
+                &mut self.data,
+                &primitive.vertices,
+                &primitive.indices
+            )?;
+            self.data.meshes.push(mesh);
+            model.meshes.push(self.data.meshes.len() - 1);
+        }
 
-unsafe fn check_physical_device(
-    instance: &Instance,
-    data: &AppData,
-    physical_device: vk::PhysicalDevice,
-) -> Result<usize, MyError>
-{
-    QueueFamilyIndices::get(
-        instance,
-        data,
-        physical_device
-    )?;
-    check_physical_device_extensions(
-        instance,
-        physical_device
-    )?;
+        // Textures
 
-    let properties = instance
-        .get_physical_device_properties(physical_device);
-    
-    let features = instance
-        .get_physical_device_features(physical_device);
-    
+        let mut image_textures = HashMap::new();
+        for image in document.primitives.iter().filter_map(|p| p.base_color_image) {
+            if image_textures.contains_key(&image) {
+                continue;
+            }
 
-    let result = match properties.device_type {
-        vk::PhysicalDeviceType::DISCRETE_GPU => Ok(0),
-        vk::PhysicalDeviceType::INTEGRATED_GPU => Ok(1),
-        vk::PhysicalDeviceType::VIRTUAL_GPU => Ok(2),
-        vk::PhysicalDeviceType::CPU => Ok(3),
-        _ => Err("No physical device suitable!".into())
-    };
-    
-    if features.geometry_shader != vk::TRUE {
-        return Err("Physical Device not suitable! (Missing Geometry Shader Support)".into());
-    }
-    else if features.sampler_anisotropy != vk::TRUE {
-        return Err("No sampler anisotropy!".into());
-    }
+            let image_data = &document.images[image];
+            let texture = create_texture_from_pixels(
+                &self.instance,
+                &self.device,
+                &mut self.data,
+                image_data.width,
+                image_data.height,
+                &image_data.pixels
+            )?;
+            self.data.textures.push(texture);
+            image_textures.insert(image, self.data.textures.len() - 1);
+            model.textures.push(self.data.textures.len() - 1);
+        }
 
-    let support = SwapchainSupport::get(instance, data, physical_device)?;
-    if support.formats.is_empty() || support.present_modes.is_empty() {
-        return Err("Insufficient swapchain support!".into());
-    }
-    
-    info!("Checking Physical Device:\n  Name: {}\n  Type: {:?}", properties.device_name, properties.device_type);
+        // Instances
 
-    result
-}
+        for (node, primitives) in model.nodes.iter_mut().zip(document.node_primitives.iter()) {
+            for primitive in primitives {
+                let texture = match document.primitives[*primitive].base_color_image {
+                    Some(image) => image_textures[&image],
+                    None => get_white_texture(&self.instance, &self.device, &mut self.data)?,
+                };
 
-unsafe fn check_physical_device_extensions(
-    instance: &Instance,
-    physical_device: vk::PhysicalDevice,
-) -> Result<(), MyError>
+                let instance = MeshInstance::new(model.meshes[*primitive], node.world_transform)
+                    .with_texture(texture);
+
+                node.instances.push(self.data.scene.add_instance(instance));
+            }
+        }
+
+        self.resources.push(ResourceCommand::LoadGltf(path.clone()));
+        self.wait_for_uploads()?;
+
+        info!("Loaded glTF {} ({} meshes, {} textures, {} nodes)", path.display(), model.meshes.len(), model.textures.len(), model.nodes.len());
+
+        Ok(model)
+    }
+
+    /// Gets the app rendering again after an error for which
+    /// [`MyError::is_recoverable`] is `true`. A lost device recreates every Vulkan
+    /// object and reloads the meshes and textures, compute targets are dispatched
+    /// again.
+    pub unsafe fn recover(&mut self, window: &Window, error: &MyError) -> Result<(), MyError> {
+        match error {
+            MyError::OutOfDate => self.recreate_swapchain(window),
+            MyError::SurfaceLost => self.recreate_surface(window),
+            MyError::DeviceLost => self.recreate_device(window),
+            _ => Err(format!("Can't recover from: {}", error).into()),
+        }
+    }
+
+    /// Sets the MSAA sample count, clamped to what the device supports. `1`
+    /// disables MSAA.
+    pub unsafe fn set_msaa(&mut self, samples: u32) -> Result<(), MyError> {
+        let samples = get_msaa_samples(&self.instance, &self.data, Some(samples));
+        if samples == self.data.msaa_samples {
+            return Ok(());
+        }
+
+        self.device.device_wait_idle()?;
+        self.destroy_render_targets();
+        self.data.msaa_samples = samples;
+        create_render_graph(&self.instance, &self.device, &mut self.data)?;
+        (self.data.pipeline_layout, self.data.pipeline) = create_pipeline(&self.device, &self.data)?;
+
+        info!("MSAA set to {}x", self.get_msaa());
+
+        Ok(())
+    }
+    pub fn get_msaa(&self) -> u32 {
+        self.data.msaa_samples.bits()
+    }
+
+    /// Recreates the swapchain with `present_mode`, or the closest mode the surface
+    /// supports. Headless apps only remember it.
+    pub unsafe fn set_present_mode(&mut self, window: &Window, present_mode: PresentMode) -> Result<(), MyError> {
+        if present_mode == self.data.present_mode {
+            return Ok(());
+        }
+
+        self.data.present_mode = present_mode;
+
+        if !self.data.headless {
+            self.recreate_swapchain(window)?;
+        }
+
+        info!("Present mode set to {:?}", present_mode);
+
+        Ok(())
+    }
+    pub fn get_present_mode(&self) -> PresentMode {
+        self.data.present_mode
+    }
+
+    pub fn get_enabled_features(&self) -> EnabledFeatures {
+        self.data.features
+    }
+
+    pub fn get_memory_stats(&self) -> MemoryStats {
+        self.data.allocator.get_stats()
+    }
+
+    /// Creates a `width`x`height` storage image filled by `compute.glsl`, returned as
+    /// a texture scene instances can sample.
+    pub unsafe fn create_compute_target(&mut self, width: u32, height: u32) -> Result<TextureId, MyError> {
+        let (texture, storage_descriptor_set) = compute::create_compute_target(
+            &self.instance,
+            &self.device,
+            &mut self.data,
+            width,
+            height
+        )?;
+        self.data.textures.push(texture);
+
+        let id = self.data.textures.len() - 1;
+        self.data.compute_targets.insert(id, storage_descriptor_set);
+        self.resources.push(ResourceCommand::CreateComputeTarget(width, height));
+        self.dispatch_compute(id)?;
+
+        Ok(id)
+    }
+
+    /// Runs `compute.glsl` over the whole compute `target` and waits for it to finish.
+    pub unsafe fn dispatch_compute(&mut self, target: TextureId) -> Result<(), MyError> {
+        let storage_descriptor_set = self.get_compute_target(target)?;
+
+        // The graphics queue may still be sampling the previous result
+        self.device.device_wait_idle()?;
+
+        compute::dispatch(&self.device, &self.data, &self.data.textures[target], storage_descriptor_set)
+    }
+
+    /// Reads the last result of the compute `target` back to the CPU.
+    pub unsafe fn read_compute_target(&mut self, target: TextureId) -> Result<image::RgbaImage, MyError> {
+        self.get_compute_target(target)?;
+
+        self.device.device_wait_idle()?;
+
+        let texture = self.data.textures[target];
+        compute::read_compute_target(&self.device, &mut self.data, &texture)
+    }
+
+    /// Recompiles the GLSL sources and rebuilds the pipelines. The current shaders
+    /// and pipelines are kept if compiling or creating either pipeline fails.
+    pub unsafe fn reload_shaders(&mut self) -> Result<(), MyError> {
+        let shaders = (
+            self.data.vertex_shader.clone(),
+            self.data.fragment_shader.clone(),
+            self.data.compute_shader.clone(),
+        );
+        compile_shaders(&self.config, &mut self.data)?;
+
+        let pipelines = create_pipeline(&self.device, &self.data).and_then(|(layout, pipeline)| {
+            compute::create_compute_pipeline(&self.device, &self.data)
+                .map(|compute| ((layout, pipeline), compute))
+                .inspect_err(|_| {
+                    self.device.destroy_pipeline(pipeline, None);
+                    self.device.destroy_pipeline_layout(layout, None);
+                })
+        });
+
+        let (pipeline, compute_pipeline) = match pipelines {
+            Ok(pipelines) => pipelines,
+            Err(e) => {
+                (self.data.vertex_shader, self.data.fragment_shader, self.data.compute_shader) = shaders;
+                return Err(e);
+            }
+        };
+
+        // Both were created, the old ones can go
+        self.device.device_wait_idle()?;
+        self.device.destroy_pipeline(self.data.pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.compute_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.compute_pipeline_layout, None);
+        (self.data.pipeline_layout, self.data.pipeline) = pipeline;
+        (self.data.compute_pipeline_layout, self.data.compute_pipeline) = compute_pipeline;
+
+        info!("Shaders reloaded");
+
+        Ok(())
+    }
+
+    pub fn get_scene(&self) -> &Scene {
+        &self.data.scene
+    }
+    pub fn get_scene_mut(&mut self) -> &mut Scene {
+        &mut self.data.scene
+    }
+    pub fn set_scene(&mut self, scene: Scene) {
+        self.data.scene = scene;
+    }
+
+    /// Renders a single frame into the offscreen image of a headless [`App`] and
+    /// reads it back to the CPU.
+    pub unsafe fn render_headless(&mut self) -> Result<image::RgbaImage, MyError> {
+        if !self.data.headless {
+            return Err("render_headless requires an App created with create_headless!".into());
+        }
+
+        self.camera.on_update(&self.input);
+        self.poll_uploads()?;
+        let in_flight_fence = self.data.in_flight_fences[self.frame];
+
+        self.device.wait_for_fences(&[in_flight_fence], true, u64::MAX)?;
+
+        self.update_uniform_buffer(0)?;
+        let command_buffer = self.record_frame(0, None, |_| Ok(()))?;
+
+        let command_buffers = &[command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(command_buffers);
+
+        self.device.reset_fences(&[in_flight_fence])?;
+
+        self.device
+            .queue_submit(self.data.graphics_queue, &[submit_info], in_flight_fence)?;
+
+        self.device.wait_for_fences(&[in_flight_fence], true, u64::MAX)?;
+
+        let image = read_offscreen_image(&self.device, &mut self.data)?;
+
+        // Screenshots keep the bit depth of the output format
+        if let Some(path) = self.screenshot_path.take() {
+            image.save(&path)
+                .map_err(|e| format!("Failed to save screenshot {}: {}", path.display(), e))?;
+        }
+
+        Ok(image.to_rgba8())
+    }
+
+    /// Saves the next rendered frame to `path`, encoded according to its extension.
+    /// Windowed apps copy the swapchain image, headless apps the offscreen image.
+    pub fn capture_screenshot(&mut self, path: impl Into<PathBuf>) {
+        self.screenshot_path = Some(path.into());
+    }
+
+    // Callbacks
+    pub fn mouse_scrolled_callback(&mut self, x: f32, y: f32) {
+        self.camera.mouse_scrolled_callback(x, y);
+    }
+
+    // PRIVATE
+    unsafe fn build(
+        entry: Entry,
+        instance: Instance,
+        device: Device,
+        mut data: AppData,
+        config: &AppConfig,
+    ) -> Result<Self, MyError>
+    {
+        data.msaa_samples = get_msaa_samples(&instance, &data, config.msaa_samples);
+        create_render_graph(&instance, &device, &mut data)?;
+        create_descriptor_set_layout(&device, &mut data)?;
+        let shader_watcher = load_shaders(config, &mut data)?;
+        pipeline_cache::create_pipeline_cache(&instance, &device, &mut data, config.pipeline_cache_path.as_deref())?;
+        (data.pipeline_layout, data.pipeline) = create_pipeline(&device, &data)?;
+        compute::create_compute_descriptor_set_layout(&device, &mut data)?;
+        (data.compute_pipeline_layout, data.compute_pipeline) = compute::create_compute_pipeline(&device, &data)?;
+        create_command_pool(&instance, &device, &mut data)?;
+        compute::create_compute_command_pool(&instance, &device, &mut data)?;
+        transfer::create_transfer_command_pool(&instance, &device, &mut data)?;
+        create_texture_sampler(&device, &mut data)?;
+        create_texture_descriptor_pool(&device, &mut data)?;
+        compute::create_compute_descriptor_pool(&device, &mut data)?;
+        let texture = create_texture(&instance, &device, &mut data, &config.get_texture_path()?)?;
+        data.textures.push(texture);
+        let mesh = create_obj_mesh(&instance, &device, &mut data, &config.get_model_path()?)?;
+        data.meshes.push(mesh);
+        data.scene.add_instance(MeshInstance::new(
+            DEFAULT_MESH,
+            glm::rotate(
+                &glm::Mat4::identity(), 
+                vmm::to_degrees(90.0) as f32, 
+                &glm::vec3(0.0, 1.0, 1.0)
+            )
+        ));
+        transfer::wait_for_uploads(&device, &mut data)?;
+        create_uniform_buffers(&instance, &device, &mut data)?;
+        create_descriptor_pool(&device, &mut data)?;
+        create_descriptor_sets(&device, &mut data)?;
+        create_frame_command_pools(&instance, &device, &mut data)?;
+        create_sync_objects(&device, &mut data)?;
+        
+        let camera = Camera::new(
+            vmm::to_radians(45.0) as f32, 
+            data.swapchain_extent.width as f32, 
+            data.swapchain_extent.height as f32, 
+            0.1, 
+            100.0
+        );
+        
+        let input = Input::new();
+
+        Ok(Self {
+            entry,
+            instance,
+            data,
+            device,
+            frame: 0,
+            resized: false,
+            start: Instant::now(),
+            camera,
+            input,
+            config: config.clone(),
+            shader_watcher,
+            screenshot_path: None,
+            resources: Vec::new(),
+            destroyed: false,
+        })
+    }
+    
+    unsafe fn update_uniform_buffer(&mut self, image_index: usize) -> Result<(), MyError>
+    {
+        let frame = self.data.frame_uniforms[image_index];
+
+        let view = self.camera.get_view_matrix();
+
+        let proj = self.camera.get_projection_matrix();
+
+        let ubo = UniformBufferObject { view: view.clone(), proj };
+
+        // Copy
+
+        self.data.uniform_ring.write(frame.camera, &ubo)?;
+
+        // Lights
+
+        let lights = self.get_lights_uniform();
+
+        self.data.uniform_ring.write(frame.lights, &lights)?;
+
+        Ok(())
+    }
+
+    fn get_lights_uniform(&self) -> LightsUniform {
+        let scene = &self.data.scene;
+        let position = self.camera.get_position();
+
+        let (directional_direction, directional_color) = match scene.directional_light {
+            Some(light) => {
+                let direction = light.direction.normalize();
+                let color = light.color * light.intensity;
+
+                (
+                    glm::vec4(direction.x, direction.y, direction.z, 1.0),
+                    glm::vec4(color.x, color.y, color.z, 0.0),
+                )
+            },
+            None => (glm::Vec4::zeros(), glm::Vec4::zeros()),
+        };
+
+        let mut point_lights = [PointLightUniform::default(); MAX_POINT_LIGHTS];
+        for (uniform, light) in point_lights.iter_mut().zip(scene.point_lights.iter()) {
+            let color = light.color * light.intensity;
+
+            uniform.position = glm::vec4(light.position.x, light.position.y, light.position.z, light.range);
+            uniform.color = glm::vec4(color.x, color.y, color.z, 0.0);
+        }
+
+        LightsUniform {
+            ambient: glm::vec4(scene.ambient_light.x, scene.ambient_light.y, scene.ambient_light.z, 0.0),
+            directional_direction,
+            directional_color,
+            camera_position: glm::vec4(position.x, position.y, position.z, 1.0),
+            point_lights,
+            point_light_count: glm::UVec4::new(scene.point_lights.len().min(MAX_POINT_LIGHTS) as u32, 0, 0, 0),
+        }
+    }
+
+    #[rustfmt::skip]
+    unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<(), MyError> {
+        self.device.device_wait_idle()?;
+        self.destroy_swapchain();
+        self.create_swapchain_objects(window)
+    }
+
+    /// Creates a new surface for `window`, assuming the queue family presenting to
+    /// the old one supports it too.
+    unsafe fn recreate_surface(&mut self, window: &Window) -> Result<(), MyError> {
+        self.device.device_wait_idle()?;
+        self.destroy_swapchain();
+        self.instance.destroy_surface_khr(self.data.surface, None);
+        self.data.surface = vk_window::create_surface(&self.instance, &window, &window)?;
+        self.create_swapchain_objects(window)
+    }
+
+    /// Replaces the app with a new one on a new device, reloading every resource and
+    /// keeping the scene, camera and input.
+    unsafe fn recreate_device(&mut self, window: &Window) -> Result<(), MyError> {
+        self.destroy();
+
+        let config = AppConfig {
+            msaa_samples: Some(self.get_msaa()),
+            present_mode: self.get_present_mode(),
+            ..self.config.clone()
+        };
+        let mut app = App::create(window, &config)?;
+
+        let result = self.resources
+            .iter()
+            .try_for_each(|r| app.load_resource(r))
+            .and_then(|_| app.wait_for_uploads());
+
+        if let Err(e) = result {
+            app.destroy();
+            return Err(e);
+        }
+
+        app.data.scene = self.data.scene.clone();
+        app.camera = self.camera;
+        app.input = self.input.clone();
+        app.start = self.start;
+        app.config = self.config.clone();
+        *self = app;
+
+        info!("Recreated the device, reloaded {} resources", self.resources.len());
+
+        Ok(())
+    }
+
+    unsafe fn load_resource(&mut self, resource: &ResourceCommand) -> Result<(), MyError> {
+        match resource {
+            ResourceCommand::AddMesh(path) => self.add_mesh_async(path).map(|_| ()),
+            ResourceCommand::ReplaceMesh(id, path) => self.replace_mesh(*id, path),
+            ResourceCommand::AddDefaultRectangle => self.add_default_rectangle().map(|_| ()),
+            ResourceCommand::AddTexture(path) => self.add_texture_async(path).map(|_| ()),
+            ResourceCommand::LoadGltf(path) => self.load_gltf(path).map(|_| ()),
+            ResourceCommand::CreateComputeTarget(width, height) => {
+                self.create_compute_target(*width, *height).map(|_| ())
+            }
+        }
+    }
+
+    #[rustfmt::skip]
+    unsafe fn create_swapchain_objects(&mut self, window: &Window) -> Result<(), MyError> {
+        create_swapchain(window, &self.instance, &self.device, &mut self.data)?;
+        create_swapchain_image_views(&self.device, &mut self.data)?;
+        create_render_graph(&self.instance, &self.device, &mut self.data)?;
+        (self.data.pipeline_layout, self.data.pipeline) = create_pipeline(&self.device, &self.data)?;
+        create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
+        create_descriptor_pool(&self.device, &mut self.data)?;
+        create_descriptor_sets(&self.device, &mut self.data)?;
+        self.data.images_in_flight.resize(self.data.swapchain_images.len(), vk::Fence::null());
+        Ok(()) 
+    }
+    
+    fn get_compute_target(&self, target: TextureId) -> Result<vk::DescriptorSet, MyError> {
+        self.data.compute_targets
+            .get(&target)
+            .copied()
+            .ok_or_else(|| format!("Texture {} is not a compute target!", target).into())
+    }
+
+    unsafe fn reload_shaders_if_changed(&mut self) {
+        let changed = self.shader_watcher
+            .as_mut()
+            .is_some_and(|w| w.poll());
+
+        if changed {
+            if let Err(e) = self.reload_shaders() {
+                error!("{}", e);
+            }
+        }
+    }
+
+    unsafe fn poll_uploads(&mut self) -> Result<(), MyError> {
+        transfer::poll_uploads(&self.device, &mut self.data)?;
+
+        Ok(())
+    }
+
+    /// Resets the command pool of the current frame in flight, whose fence must have
+    /// been waited on, and records the frame for `image_index`.
+    unsafe fn record_frame<F>(
+        &mut self,
+        image_index: usize,
+        screenshot: Option<&Screenshot>,
+        draw: F,
+    ) -> Result<vk::CommandBuffer, MyError>
+    where
+        F: FnOnce(&mut Frame) -> Result<(), MyError>,
+    {
+        let command_buffer = self.data.frame_command_buffers[self.frame];
+
+        self.device.reset_command_pool(
+            self.data.frame_command_pools[self.frame],
+            vk::CommandPoolResetFlags::empty()
+        )?;
+
+        record_command_buffer(&self.device, &self.data, command_buffer, image_index, screenshot, draw)?;
+
+        Ok(command_buffer)
+    }
+    
+    #[rustfmt::skip]
+    unsafe fn destroy_swapchain(&mut self)
+    {
+        self.device.destroy_descriptor_pool(self.data.descriptor_pool, None);
+        self.data.uniform_ring.destroy(&self.device, &mut self.data.allocator);
+        self.destroy_render_targets();
+        self.data.swapchain_image_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
+
+        if self.data.headless {
+            self.device.destroy_image(self.data.offscreen_image, None);
+            self.data.allocator.free(&self.device, self.data.offscreen_image_memory);
+        }
+        else {
+            self.device.destroy_swapchain_khr(self.data.swapchain, None);
+        }
+    }
+
+    /// Everything that depends on the MSAA sample count.
+    #[rustfmt::skip]
+    unsafe fn destroy_render_targets(&mut self)
+    {
+        self.data.render_graph.destroy(&self.device, &mut self.data.allocator);
+        self.device.destroy_pipeline(self.data.pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.pipeline_layout, None);
+    }
+}
+
+// Helper Functions
+unsafe fn create_instance(
+        window: Option<&Window>,
+        entry: &Entry,
+        data: &mut AppData,
+) -> Result<Instance, MyError> 
+{
+    let application_info = vk::ApplicationInfo::builder()
+        .application_name(b"Vulkan Tutorial\0")
+        .application_version(vk::make_version(1, 0, 0))
+        .engine_name(b"No Engine\0")
+        .engine_version(vk::make_version(1, 0, 0))
+        .api_version(vk::make_version(1, 0, 0));
+    
+    let available_layers = entry
+        .enumerate_instance_layer_properties()?
+        .iter()
+        .map(|l| l.layer_name)
+        .collect::<HashSet<_>>();
+    
+    if VALIDATION_ENABLED && !available_layers.contains(&VALIDATION_LAYER) {
+        return Err("Validation layer requested but not supported!".into());
+    }
+    
+    let layers = if VALIDATION_ENABLED {
+        vec![VALIDATION_LAYER.as_ptr()]
+    }
+    else {
+        Vec::new()
+    };
+
+    let mut extensions = match window {
+        Some(window) => vk_window::get_required_instance_extensions(window)
+            .iter()
+            .map(|e| e.as_ptr())
+            .collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+    
+    if VALIDATION_ENABLED {
+        extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
+    }
+
+    // HDR and wide-gamut color spaces
+    data.colorspace_extension = window.is_some() && entry
+        .enumerate_instance_extension_properties(None)?
+        .iter()
+        .any(|e| e.extension_name == vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name);
+
+    if data.colorspace_extension {
+        extensions.push(vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name.as_ptr());
+    }
+
+    // Required by Vulkan SDK on macOS since 1.3.216
+    let flags = if 
+        cfg!(target_os = "macos") &&
+        entry.version()? >= PORTABILITY_MACOS_VERSION
+    {
+        info!("Enabling extensions for macOS portablilty!");
+        extensions.push(vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION.name.as_ptr());
+        extensions.push(vk::KHR_PORTABILITY_ENUMERATION_EXTENSION.name.as_ptr());
+        
+        vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+    } 
+    else {
+        vk::InstanceCreateFlags::empty()
+    };
+    
+    let mut info = vk::InstanceCreateInfo::builder()
+        .application_info(&application_info)
+        .enabled_layer_names(&layers)
+        .enabled_extension_names(&extensions)
+        .flags(flags);
+        
+    let mut debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
+        .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
+        .user_callback(Some(debug_callback));
+    
+    if VALIDATION_ENABLED {
+        info = info.push_next(&mut debug_info);
+    }
+    
+    let instance = entry.create_instance(&info, None)?;
+    Ok(instance)
+}
+
+/// Picks the device matching `selector`, or the suitable device with the highest
+/// [`check_physical_device`] score.
+unsafe fn pick_physical_device(
+    instance: &Instance,
+    data: &mut AppData,
+    selector: Option<&DeviceSelector>,
+) -> Result<(), MyError>
+{
+    let mut selected = None;
+
+    for (index, physical_device) in instance.enumerate_physical_devices()?.into_iter().enumerate() {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let name = properties.device_name.to_string();
+
+        let skipped = match selector {
+            Some(DeviceSelector::Index(i)) => *i != index,
+            Some(DeviceSelector::Name(n)) => !name.to_lowercase().contains(&n.to_lowercase()),
+            None => false,
+        };
+        if skipped {
+            info!("Skipping Physical Device {} ({}), it doesn't match {:?}", index, name, selector.unwrap());
+            continue;
+        }
+
+        match check_physical_device(instance, data, physical_device) {
+            Ok(score) => {
+                info!("Physical Device {} ({}) scored {}", index, name, score);
+
+                if selected.is_none_or(|(s, _)| score > s) {
+                    selected = Some((score, physical_device));
+                }
+            },
+            Err(e) => warn!("Rejected Physical Device {} ({}): {}", index, name, e),
+        }
+    }
+
+    let Some((_, physical_device)) = selected else {
+        return Err(match selector {
+            Some(selector) => format!("No suitable Physical Device matches {:?}!", selector).into(),
+            None => "Failed to find any suitable Physical Device!".into(),
+        });
+    };
+
+    let properties = instance.get_physical_device_properties(physical_device);
+    warn!("Physical Selected:\n  Name: {}\n  Type: {:?}", properties.device_name, properties.device_type);
+    data.physical_device = physical_device;
+
+    Ok(())
+}
+
+/// Fails with the reason the device can't be used, otherwise scores it by type
+/// and then by device local memory.
+unsafe fn check_physical_device(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Result<u64, MyError>
+{
+    QueueFamilyIndices::get(
+        instance,
+        data,
+        physical_device
+    )?;
+    check_physical_device_extensions(
+        instance,
+        data,
+        physical_device
+    )?;
+
+    let properties = instance
+        .get_physical_device_properties(physical_device);
+    
+    let type_score = match properties.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        // OTHER devices may still render, they are just picked last
+        _ => 0,
+    };
+    
+    EnabledFeatures::negotiate(instance, physical_device)?;
+
+    if !data.headless {
+        let support = SwapchainSupport::get(instance, data, physical_device)?;
+        if support.formats.is_empty() || support.present_modes.is_empty() {
+            return Err("Insufficient swapchain support!".into());
+        }
+    }
+    
+    // Type first, memory in MiB only breaks ties
+    let memory = instance.get_physical_device_memory_properties(physical_device);
+    let device_local = memory.memory_heaps[..memory.memory_heap_count as usize]
+        .iter()
+        .filter(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|h| h.size)
+        .max()
+        .unwrap_or(0);
+
+    Ok((type_score << 32) + (device_local >> 20).min(u32::MAX as u64))
+}
+
+unsafe fn check_physical_device_extensions(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Result<(), MyError>
 {
     let extensions = instance
         .enumerate_device_extension_properties(physical_device, None)?
@@ -664,31 +1528,45 @@ unsafe fn check_physical_device_extensions(
         .map(|e| e.extension_name)
         .collect::<HashSet<_>>(); 
     
-    if DEVICE_EXTENSIONS.iter().all(|e| extensions.contains(e)) {
+    if get_device_extensions(data).iter().all(|e| extensions.contains(e)) {
         return Ok(());
     }
 
     Err("Missing required device extensions!".into())
 }
 
-unsafe fn get_max_msaa_samples(
+fn get_device_extensions(data: &AppData) -> &'static [vk::ExtensionName] {
+    if data.headless {
+        &[]
+    }
+    else {
+        DEVICE_EXTENSIONS
+    }
+}
+
+/// Highest sample count supported by the device that does not exceed `max`
+/// (any count when `None`). `_1` disables MSAA.
+unsafe fn get_msaa_samples(
     instance: &Instance,
-    data: &AppData
+    data: &AppData,
+    max: Option<u32>,
 ) -> vk::SampleCountFlags {
     let properties = instance.get_physical_device_properties(data.physical_device);
     let counts = properties.limits.framebuffer_color_sample_counts 
         & properties.limits.framebuffer_depth_sample_counts;
+    let max = max.unwrap_or(u32::MAX);
     
     [
-        vk::SampleCountFlags::_64,
-        vk::SampleCountFlags::_32,
-        vk::SampleCountFlags::_16,
-        vk::SampleCountFlags::_8,
-        vk::SampleCountFlags::_4,
-        vk::SampleCountFlags::_2,
+        (64, vk::SampleCountFlags::_64),
+        (32, vk::SampleCountFlags::_32),
+        (16, vk::SampleCountFlags::_16),
+        (8, vk::SampleCountFlags::_8),
+        (4, vk::SampleCountFlags::_4),
+        (2, vk::SampleCountFlags::_2),
     ]
     .iter()
-    .cloned()
+    .filter(|(n, _)| *n <= max)
+    .map(|(_, c)| *c)
     .find(|c| counts.contains(*c))
     .unwrap_or(vk::SampleCountFlags::_1)
 }
@@ -704,6 +1582,8 @@ unsafe fn create_logical_device(
     let mut unique_indices = HashSet::new();
     unique_indices.insert(indices.graphics);
     unique_indices.insert(indices.present);
+    unique_indices.insert(indices.compute);
+    unique_indices.insert(indices.transfer);
     
     let queue_priorities = &[1.0];
     let queue_infos = unique_indices
@@ -722,7 +1602,7 @@ unsafe fn create_logical_device(
         vec![]
     };
     
-    let mut extensions = DEVICE_EXTENSIONS
+    let mut extensions = get_device_extensions(data)
         .iter()
         .map(|n| n.as_ptr())
         .collect::<Vec<_>>();
@@ -732,9 +1612,9 @@ unsafe fn create_logical_device(
         extensions.push(vk::KHR_PORTABILITY_SUBSET_EXTENSION.name.as_ptr());
     }
     
-    let features = vk::PhysicalDeviceFeatures::builder()
-        .sampler_anisotropy(true)
-        .sample_rate_shading(true);
+    // A KTX2/DDS file using a compressed format family that isn't enabled fails to load
+    data.features = EnabledFeatures::negotiate(instance, data.physical_device)?;
+    let features = data.features.get_device_features();
     
     let info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
@@ -752,32 +1632,43 @@ unsafe fn create_logical_device(
         indices.present,
         0
     );
+    data.compute_queue = device.get_device_queue(
+        indices.compute,
+        0
+    );
+    data.transfer_queue = device.get_device_queue(
+        indices.transfer,
+        0
+    );
     
     Ok(device)
 }
 
-fn get_swapchain_surface_format(
-    formats: &[vk::SurfaceFormatKHR]
-) -> vk::SurfaceFormatKHR
-{
-    formats
-        .iter()
-        .cloned()
-        .find(|f| {
-            f.format == vk::Format::B8G8R8A8_SRGB && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        })
-    .unwrap_or_else(|| formats[0])
-}
-
+/// The first mode of `present_mode`'s preference list that is supported, FIFO is
+/// guaranteed to be.
 fn get_swapchain_present_mode(
-    present_modes: &[vk::PresentModeKHR]
+    present_modes: &[vk::PresentModeKHR],
+    present_mode: PresentMode,
 ) -> vk::PresentModeKHR
 {
-    present_modes
+    let preferred: &[vk::PresentModeKHR] = match present_mode {
+        PresentMode::Vsync => &[],
+        PresentMode::RelaxedVsync => &[vk::PresentModeKHR::FIFO_RELAXED],
+        PresentMode::Mailbox => &[vk::PresentModeKHR::MAILBOX],
+        PresentMode::Immediate => &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX],
+    };
+
+    let mode = preferred
         .iter()
         .cloned()
-        .find(|m| *m == vk::PresentModeKHR::IMMEDIATE)
-        .unwrap_or(vk::PresentModeKHR::FIFO)
+        .find(|m| present_modes.contains(m))
+        .unwrap_or(vk::PresentModeKHR::FIFO);
+
+    if preferred.first().is_some_and(|m| *m != mode) {
+        warn!("{:?} is not supported, presenting with {:?}", present_mode, mode);
+    }
+
+    mode
 }
 
 fn get_swapchain_extent(
@@ -817,12 +1708,21 @@ unsafe fn create_swapchain(
     let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
     let support = SwapchainSupport::get(instance, data, data.physical_device)?;
 
-    let surface_format = get_swapchain_surface_format(&support.formats);
-    let present_mode = get_swapchain_present_mode(&support.present_modes);
+    let surface_format = output::get_surface_format(&support.formats, data.output_format, data.colorspace_extension);
+    let present_mode = get_swapchain_present_mode(&support.present_modes, data.present_mode);
     let extent = get_swapchain_extent(window, support.capabilities);
 
     data.swapchain_format = surface_format.format;
     data.swapchain_extent = extent;
+    data.output_encoding = OutputEncoding::get(surface_format.format, surface_format.color_space);
+
+    info!("Presenting {:?} in {:?}", surface_format.format, surface_format.color_space);
+
+    // Copying from the swapchain images is only needed for screenshots
+    data.swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    if support.capabilities.supported_usage_flags.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+        data.swapchain_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+    }
 
     let mut image_count = support.capabilities.min_image_count + 1;
     if support.capabilities.max_image_count != 0 && image_count > support.capabilities.max_image_count {
@@ -847,7 +1747,7 @@ unsafe fn create_swapchain(
         .image_color_space(surface_format.color_space)
         .image_extent(extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(data.swapchain_usage)
         .image_sharing_mode(image_sharing_mode)
         .queue_family_indices(&queue_family_indices)
         .pre_transform(support.capabilities.current_transform)
@@ -865,6 +1765,42 @@ unsafe fn create_swapchain(
     Ok(()) 
 }
 
+unsafe fn create_offscreen_target(
+    device: &Device,
+    data: &mut AppData,
+    width: u32,
+    height: u32,
+) -> Result<(), MyError>
+{
+    let (format, color_space) = output::get_offscreen_format(data.output_format);
+
+    data.swapchain_format = format;
+    data.swapchain_extent = vk::Extent2D { width, height };
+    data.output_encoding = OutputEncoding::get(format, color_space);
+    data.swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+
+    let (offscreen_image, offscreen_image_memory) = create_image(
+        device,
+        data,
+        width,
+        height,
+        1,
+        vk::SampleCountFlags::_1,
+        data.swapchain_format,
+        vk::ImageTiling::OPTIMAL,
+        data.swapchain_usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.offscreen_image = offscreen_image;
+    data.offscreen_image_memory = offscreen_image_memory;
+
+    // The offscreen image stands in for the swapchain images
+    data.swapchain_images = vec![offscreen_image];
+
+    Ok(())
+}
+
 unsafe fn create_swapchain_image_views(
     device: &Device,
     data: &mut AppData,
@@ -886,111 +1822,95 @@ unsafe fn create_swapchain_image_views(
     Ok(())
 }
 
-unsafe fn create_render_pass(
+/// Declares the passes of a frame. New passes are added here, the graph orders
+/// them and creates their attachments, render passes and framebuffers.
+unsafe fn create_render_graph(
     instance: &Instance,
     device: &Device,
     data: &mut AppData
 ) -> Result<(), MyError>
 {
-     // Attachments
-
-    let color_attachment = vk::AttachmentDescription::builder()
-        .format(data.swapchain_format)
-        .samples(data.msaa_samples)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::STORE)
-        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
-
-    let depth_stencil_attachment = vk::AttachmentDescription::builder()
-        .format(get_depth_format(instance, data)?)
-        .samples(data.msaa_samples)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
-
-    let color_resolve_attachment = vk::AttachmentDescription::builder()
-        .format(data.swapchain_format)
-        .samples(vk::SampleCountFlags::_1)
-        .load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .store_op(vk::AttachmentStoreOp::STORE)
-        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+    let mut graph = RenderGraph::default();
 
-    // Subpasses
-
-    let color_attachment_ref = vk::AttachmentReference::builder()
-        .attachment(0)
-        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
-
-    let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
-        .attachment(1)
-        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+    let present_layout = if data.headless {
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+    } else {
+        vk::ImageLayout::PRESENT_SRC_KHR
+    };
 
-    let color_resolve_attachment_ref = vk::AttachmentReference::builder()
-        .attachment(2)
-        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    let target = graph.import_swapchain("target", data.swapchain_format, present_layout);
+    let depth = graph.add_transient(
+        "depth",
+        get_depth_format(instance, data)?,
+        data.msaa_samples,
+        data.swapchain_extent,
+    );
 
-    let color_attachments = &[color_attachment_ref];
-    let resolve_attachments = &[color_resolve_attachment_ref];
-    let subpass = vk::SubpassDescription::builder()
-        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-        .color_attachments(color_attachments)
-        .depth_stencil_attachment(&depth_stencil_attachment_ref)
-        .resolve_attachments(resolve_attachments);
+    let color_clear = Load::Clear(vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: data.output_encoding.encode([0.1, 0.1, 0.1, 1.0])
+        }
+    });
+    let depth_clear = Load::Clear(vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: 1.0,
+            stencil: 0,
+        },
+    });
 
-    // Dependencies
+    // Without MSAA the swapchain image is rendered to directly
+    let scene = if data.msaa_samples == vk::SampleCountFlags::_1 {
+        PassDesc::new("scene").with_color(target, color_clear)
+    } else {
+        let color = graph.add_transient("color", data.swapchain_format, data.msaa_samples, data.swapchain_extent);
 
-    let dependency = vk::SubpassDependency::builder()
-        .src_subpass(vk::SUBPASS_EXTERNAL)
-        .dst_subpass(0)
-        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
-        .src_access_mask(vk::AccessFlags::empty())
-        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
-        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+        PassDesc::new("scene")
+            .with_color(color, color_clear)
+            .with_resolve(target)
+    };
 
-    // Create
+    data.scene_pass = graph.add_pass(scene.with_depth(depth, depth_clear));
 
-    let attachments = &[color_attachment, depth_stencil_attachment, color_resolve_attachment];
-    let subpasses = &[subpass];
-    let dependencies = &[dependency];
-    let info = vk::RenderPassCreateInfo::builder()
-        .attachments(attachments)
-        .subpasses(subpasses)
-        .dependencies(dependencies);
+    graph.compile(device, data)?;
 
-    data.render_pass = device.create_render_pass(&info, None)?;
+    data.render_pass = graph.get_render_pass(data.scene_pass);
+    data.render_graph = graph;
 
     Ok(())
 }
 
+/// Creates the scene pipeline and its layout without replacing the current ones.
 unsafe fn create_pipeline(
     device: &Device,
-    data: &mut AppData
-) -> Result<(), MyError>
+    data: &AppData
+) -> Result<(vk::PipelineLayout, vk::Pipeline), MyError>
 {
-    let vert = include_bytes!("../assets/shaders/compiled/vertex.spv");
-    let frag = include_bytes!("../assets/shaders/compiled/fragment.spv");
-
-    let vert_module = create_shader_module(device, &vert[..])?;
-    let frag_module = create_shader_module(device, &frag[..])?;
+    let vert_module = create_shader_module(device, &data.vertex_shader)?;
+    let frag_module = match create_shader_module(device, &data.fragment_shader) {
+        Ok(module) => module,
+        Err(e) => {
+            device.destroy_shader_module(vert_module, None);
+            return Err(e);
+        }
+    };
 
     let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX)
         .module(vert_module)
         .name(b"main\0");
     
+    // Encodes the output for the swapchain format and color space
+    let output_constants = OutputConstants::new(data.output_encoding);
+    let map_entries = OutputConstants::get_map_entries();
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(&map_entries)
+        .data(output_constants.as_bytes());
+
     let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::FRAGMENT)
         .module(frag_module)
-        .name(b"main\0");
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
     
     let binding_descriptions = &[Vertex::binding_description()];
     let attribute_descriptions = Vertex::attribute_descritptions();
@@ -1030,7 +1950,7 @@ unsafe fn create_pipeline(
         .depth_bias_enable(false);
         
     let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
-        .sample_shading_enable(true)
+        .sample_shading_enable(data.msaa_samples != vk::SampleCountFlags::_1 && data.features.sample_rate_shading)
         .min_sample_shading(0.2)
         .rasterization_samples(data.msaa_samples);
     
@@ -1060,11 +1980,23 @@ unsafe fn create_pipeline(
         .attachments(attachments)
         .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-    let set_layouts = &[data.descriptor_set_layout];
+    let vert_push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(size_of::<glm::Mat4>() as u32);
+
+    let set_layouts = &[data.descriptor_set_layout, data.texture_descriptor_set_layout];
+    let push_constant_ranges = &[vert_push_constant_range];
     let layout_info = vk::PipelineLayoutCreateInfo::builder()
-        .set_layouts(set_layouts);
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    let pipeline_layout = device.create_pipeline_layout(&layout_info, None);
 
-    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+    // Set every frame, see `Frame::set_viewport`
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+        .dynamic_states(dynamic_states);
 
     let stages = &[vert_stage, frag_stage];
     let info = vk::GraphicsPipelineCreateInfo::builder()
@@ -1076,47 +2008,26 @@ unsafe fn create_pipeline(
         .multisample_state(&multisample_state)
         .depth_stencil_state(&depth_stencil_state)
         .color_blend_state(&color_blend_state)
-        .layout(data.pipeline_layout)
+        .dynamic_state(&dynamic_state)
         .render_pass(data.render_pass)
         .subpass(0);
 
-    data.pipeline = device.create_graphics_pipelines(
-        vk::PipelineCache::null(), 
-        &[info], 
-        None
-    )?.0[0];
+    let pipeline = pipeline_layout.and_then(|layout| {
+        let info = info.layout(layout);
+
+        match device.create_graphics_pipelines(data.pipeline_cache, &[info], None) {
+            Ok((pipelines, _)) => Ok((layout, pipelines[0])),
+            Err(e) => {
+                device.destroy_pipeline_layout(layout, None);
+                Err(e)
+            }
+        }
+    });
 
     device.destroy_shader_module(vert_module, None);
     device.destroy_shader_module(frag_module, None);
 
-    Ok(())
-}
-
-unsafe fn create_framebuffers(
-    device: &Device,
-    data: &mut AppData
-) -> Result<(), MyError>
-{
-    data.framebuffers = data.swapchain_image_views
-        .iter()
-        .map(|i| {
-            let attachments = &[
-                data.color_image_view,
-                data.depth_image_view,
-                *i,
-            ];
-            let create_info = vk::FramebufferCreateInfo::builder()
-                .render_pass(data.render_pass)
-                .attachments(attachments)
-                .width(data.swapchain_extent.width)
-                .height(data.swapchain_extent.height)
-                .layers(1);
-
-            device.create_framebuffer(&create_info, None)
-        })
-        .collect::<Result<Vec<_>, _>>()?;
-
-    Ok(())
+    Ok(pipeline?)
 }
 
 unsafe fn create_command_pool(
@@ -1140,60 +2051,56 @@ unsafe fn create_command_pool(
     Ok(())
 }
 
-unsafe fn generate_mipmaps(
+unsafe fn create_frame_command_pools(
     instance: &Instance,
     device: &Device,
-    data: &AppData,
-    image: vk::Image,
-    format: vk::Format,
-    width: u32,
-    height: u32,
-    mip_levels: u32
+    data: &mut AppData
 ) -> Result<(), MyError>
 {
-    // TODO: Aparently it's not common to generate mipmaps in the runtime, so you must find a way to do that in the texture itself!
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
 
-    if !instance
-        .get_physical_device_format_properties(data.physical_device, format)
-        .optimal_tiling_features
-        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
-    {
-        return Err("Texture image format does not support linear blitting!".into());
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        let info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(indices.graphics);
+
+        let command_pool = device.create_command_pool(&info, None)?;
+
+        let info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+
+        data.frame_command_pools.push(command_pool);
+        data.frame_command_buffers.push(device.allocate_command_buffers(&info)?[0]);
     }
 
-    let command_buffer = begin_single_time_commands(device, data)?;
-    
-    let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
-        .base_array_layer(0)
-        .layer_count(1)
-        .level_count(1);
-    
-    let mut barrier = vk::ImageMemoryBarrier::builder()
-        .image(image)
-        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .subresource_range(subresource);
+    Ok(())
+}
+
+/// Records the blits filling mip levels `1..mip_levels` from level 0. Expects every
+/// level in `TRANSFER_DST_OPTIMAL` and leaves them in `SHADER_READ_ONLY_OPTIMAL`.
+unsafe fn generate_mipmaps(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    mip_levels: u32
+) -> Result<(), MyError>
+{
+    let transition = |level: u32, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout| {
+        ImageTransition::new(image, format, old_layout, new_layout)
+            .with_mip_levels(level, 1)
+            .record(device, command_buffer)
+    };
 
     let mut mip_width = width;
     let mut mip_height = height;
     
     for i in 1..mip_levels {
-        barrier.subresource_range.base_mip_level = i - 1;
-        barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-        barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-        barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-        barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
-        
-        device.cmd_pipeline_barrier(
-            command_buffer, 
-            vk::PipelineStageFlags::TRANSFER, 
-            vk::PipelineStageFlags::TRANSFER, 
-            vk::DependencyFlags::empty(), 
-            &[] as &[vk::MemoryBarrier],
-            &[] as &[vk::BufferMemoryBarrier],
-            &[barrier],
-        );
+        transition(i - 1, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)?;
         
         let src_subresource = vk::ImageSubresourceLayers::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -1237,88 +2144,92 @@ unsafe fn generate_mipmaps(
             vk::Filter::LINEAR,
         );
         
-        barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-        barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-        barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
-        barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-        
-        device.cmd_pipeline_barrier(
-            command_buffer, 
-            vk::PipelineStageFlags::TRANSFER, 
-            vk::PipelineStageFlags::FRAGMENT_SHADER, 
-            vk::DependencyFlags::empty(), 
-            &[] as &[vk::MemoryBarrier], 
-            &[] as &[vk::BufferMemoryBarrier], 
-            &[barrier],
-        );
+        transition(i - 1, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)?;
         
         if mip_width > 1 { mip_width /= 2 }
         if mip_height > 1 { mip_height /= 2 }
     }
     
-    barrier.subresource_range.base_mip_level = mip_levels - 1;
-    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-    barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-    barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-
-    device.cmd_pipeline_barrier(
-        command_buffer,
-        vk::PipelineStageFlags::TRANSFER,
-        vk::PipelineStageFlags::FRAGMENT_SHADER,
-        vk::DependencyFlags::empty(),
-        &[] as &[vk::MemoryBarrier],
-        &[] as &[vk::BufferMemoryBarrier],
-        &[barrier],
-    );
-
-    end_single_time_commands(device, data, command_buffer)?;
-
-    Ok(())
+    transition(mip_levels - 1, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
 }
 
-unsafe fn create_texture_image(
+/// Loads `.ktx2` and `.dds` files with their own mip chain, anything else through
+/// the `image` crate.
+unsafe fn create_texture(
     instance: &Instance,
     device: &Device,
-    data: &mut AppData
-) -> Result<(), MyError>
+    data: &mut AppData,
+    path: &Path,
+) -> Result<Texture, MyError>
 {
-    let image = image::io::Reader::open("C:/users/renat/personal/learn_vk/learn_vk/assets/textures/viking_room.png")?.decode()?;
-    
-    let width = image.width();
-    let height = image.height();
-    let pixels = image.as_bytes();
-    let size = (pixels.len() * size_of::<u8>()) as u64;
-    data.mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+    if texture_loader::is_precompiled(path) {
+        let levels = texture_loader::read_texture(path)?;
+        let texture = create_texture_from_levels(instance, device, data, &levels)?;
+
+        info!(
+            "Loaded texture {} ({}x{}, {} mips, {:?})",
+            path.display(),
+            levels.width,
+            levels.height,
+            texture.mip_levels,
+            levels.format
+        );
 
-    // Create (staging)
+        return Ok(texture);
+    }
 
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
+    let image = image::io::Reader::open(path)?.decode()?.to_rgba8();
+    
+    let texture = create_texture_from_pixels(
         instance,
         device,
         data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        image.width(),
+        image.height(),
+        image.as_raw()
     )?;
 
-    // Copy (staging)
+    info!("Loaded texture {} ({}x{}, {} mips)", path.display(), image.width(), image.height(), texture.mip_levels);
+
+    Ok(texture)
+}
 
-    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+/// Uploads tightly packed RGBA8 sRGB `pixels` as a mipmapped texture. Mipmaps are
+/// blitted on the GPU, or built on the CPU when the format can't be blitted.
+unsafe fn create_texture_from_pixels(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> Result<Texture, MyError>
+{
+    let mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
 
-    memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
+    if !instance
+        .get_physical_device_format_properties(data.physical_device, vk::Format::R8G8B8A8_SRGB)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+    {
+        let levels = TextureLevels {
+            format: vk::Format::R8G8B8A8_SRGB,
+            width,
+            height,
+            levels: texture_loader::generate_mip_chain(width, height, pixels)?,
+        };
 
-    device.unmap_memory(staging_buffer_memory);
+        return create_texture_from_levels(instance, device, data, &levels);
+    }
 
     // Create (image)
 
     let (texture_image, texture_image_memory) = create_image(
-        instance,
         device,
         data,
         width,
         height,
-        data.mip_levels,
+        mip_levels,
         vk::SampleCountFlags::_1,
         vk::Format::R8G8B8A8_SRGB,
         vk::ImageTiling::OPTIMAL,
@@ -1328,68 +2239,145 @@ unsafe fn create_texture_image(
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    data.texture_image = texture_image;
-    data.texture_image_memory = texture_image_memory;
+    // Copy (image), mipmaps are generated once the graphics queue acquires it
+
+    let mut upload = transfer::begin_upload(instance, device, data)?;
+    transfer::upload_image(device, data, &mut upload, ImageLevels {
+        image: texture_image,
+        format: vk::Format::R8G8B8A8_SRGB,
+        width,
+        height,
+        mip_levels,
+        levels: &[pixels],
+    })?;
+    transfer::submit_upload(device, data, upload, UploadTarget::Texture(texture_image))?;
 
-    // Transition + Copy (image)
+    // Image View
 
-    transition_image_layout(
-        device,
-        data,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
-        vk::ImageLayout::UNDEFINED,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        data.mip_levels,
+    let texture_image_view = create_image_view(
+        device, 
+        texture_image, 
+        vk::Format::R8G8B8A8_SRGB, 
+        vk::ImageViewType::_2D, 
+        vk::ImageAspectFlags::COLOR,
+        mip_levels
     )?;
 
-    copy_buffer_to_image(device, data, staging_buffer, data.texture_image, width, height)?;
+    let descriptor_set = create_texture_descriptor_set(device, data, texture_image_view)?;
+
+    Ok(Texture {
+        image: texture_image,
+        image_memory: texture_image_memory,
+        image_view: texture_image_view,
+        mip_levels,
+        width,
+        height,
+        descriptor_set,
+    })
+}
+
+/// Uploads a texture whose mip chain is already built, copying every level as is.
+unsafe fn create_texture_from_levels(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    texture: &TextureLevels,
+) -> Result<Texture, MyError>
+{
+    let mip_levels = texture.levels.len() as u32;
+
+    if mip_levels == 0 {
+        return Err("Texture has no mip levels!".into());
+    }
+
+    // BC7 is usually desktop only and ASTC mobile only
+    if !instance
+        .get_physical_device_format_properties(data.physical_device, texture.format)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    {
+        return Err(format!("Texture format {:?} is not supported by the device!", texture.format).into());
+    }
+
+    // Create (image)
 
-    transition_image_layout(
+    let (texture_image, texture_image_memory) = create_image(
         device,
         data,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
-        vk::ImageLayout::UNDEFINED,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        data.mip_levels
+        texture.width,
+        texture.height,
+        mip_levels,
+        vk::SampleCountFlags::_1,
+        texture.format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    // Cleanup
+    // Copy (image)
 
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
-    
-    // Mipmaps
-    generate_mipmaps(
-        instance, 
+    let levels = texture.levels
+        .iter()
+        .map(|l| l.as_slice())
+        .collect::<Vec<_>>();
+
+    let mut upload = transfer::begin_upload(instance, device, data)?;
+    transfer::upload_image(device, data, &mut upload, ImageLevels {
+        image: texture_image,
+        format: texture.format,
+        width: texture.width,
+        height: texture.height,
+        mip_levels,
+        levels: &levels,
+    })?;
+    transfer::submit_upload(device, data, upload, UploadTarget::Texture(texture_image))?;
+
+    // Image View
+
+    let texture_image_view = create_image_view(
         device, 
-        data, 
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
-        width, 
-        height, 
-        data.mip_levels,
+        texture_image, 
+        texture.format, 
+        vk::ImageViewType::_2D, 
+        vk::ImageAspectFlags::COLOR,
+        mip_levels
     )?;
 
-    Ok(())
+    let descriptor_set = create_texture_descriptor_set(device, data, texture_image_view)?;
+
+    Ok(Texture {
+        image: texture_image,
+        image_memory: texture_image_memory,
+        image_view: texture_image_view,
+        mip_levels,
+        width: texture.width,
+        height: texture.height,
+        descriptor_set,
+    })
 }
 
-unsafe fn create_texture_image_view(
+/// 1x1 white texture for materials without a texture, created on first use.
+unsafe fn get_white_texture(
+    instance: &Instance,
     device: &Device,
-    data: &mut AppData
-) -> Result<(), MyError>
+    data: &mut AppData,
+) -> Result<TextureId, MyError>
 {
-    data.texture_image_view = create_image_view(
-        device, 
-        data.texture_image, 
-        vk::Format::R8G8B8A8_SRGB, 
-        vk::ImageViewType::_2D, 
-        vk::ImageAspectFlags::COLOR,
-        data.mip_levels
-    )?;
+    if let Some(texture) = data.white_texture {
+        return Ok(texture);
+    }
 
-    Ok(())
+    let texture = create_texture_from_pixels(instance, device, data, 1, 1, &[255, 255, 255, 255])?;
+    data.textures.push(texture);
+    data.white_texture = Some(data.textures.len() - 1);
+
+    Ok(data.textures.len() - 1)
+}
+
+unsafe fn destroy_texture(device: &Device, allocator: &mut Allocator, texture: &Texture) {
+    device.destroy_image_view(texture.image_view, None);
+    device.destroy_image(texture.image, None);
+    allocator.free(device, texture.image_memory);
 }
 
 unsafe fn create_image_view(
@@ -1418,9 +2406,8 @@ unsafe fn create_image_view(
 }
 
 unsafe fn create_image(
-    instance: &Instance,
     device: &Device,
-    data: &AppData,
+    data: &mut AppData,
     width: u32,
     height: u32,
     mip_levels: u32,
@@ -1429,7 +2416,7 @@ unsafe fn create_image(
     tiling: vk::ImageTiling,
     usage: vk::ImageUsageFlags,
     properties: vk::MemoryPropertyFlags
-) -> Result<(vk::Image, vk::DeviceMemory), MyError>
+) -> Result<(vk::Image, Allocation), MyError>
 {
     let info = vk::ImageCreateInfo::builder()
         .image_type(vk::ImageType::_2D)
@@ -1453,13 +2440,14 @@ unsafe fn create_image(
 
     let requirements = device.get_image_memory_requirements(image);
 
-    let info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(instance, data, properties, requirements)?);
-
-    let image_memory = device.allocate_memory(&info, None)?;
+    let image_memory = data.allocator.allocate(
+        device,
+        requirements,
+        properties,
+        tiling == vk::ImageTiling::LINEAR
+    )?;
 
-    device.bind_image_memory(image, image_memory, 0)?;
+    device.bind_image_memory(image, image_memory.memory, image_memory.offset)?;
 
     Ok((image, image_memory))
 }
@@ -1475,15 +2463,15 @@ unsafe fn create_texture_sampler(
         .address_mode_u(vk::SamplerAddressMode::REPEAT)
         .address_mode_v(vk::SamplerAddressMode::REPEAT)
         .address_mode_w(vk::SamplerAddressMode::REPEAT)
-        .anisotropy_enable(true)
-        .max_anisotropy(16.0)
+        .anisotropy_enable(data.features.sampler_anisotropy.is_some())
+        .max_anisotropy(data.features.sampler_anisotropy.unwrap_or(1.0))
         .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
         .unnormalized_coordinates(false)
         .compare_enable(false)
         .compare_op(vk::CompareOp::ALWAYS)
         .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
         .min_lod(0.0)
-        .max_lod(data.mip_levels as f32)
+        .max_lod(vk::LOD_CLAMP_NONE)
         .mip_lod_bias(0.0);
 
     data.texture_sampler = device.create_sampler(&info, None)?;
@@ -1491,80 +2479,6 @@ unsafe fn create_texture_sampler(
     Ok(())
 }
 
-unsafe fn create_color_objects(
-    instance: &Instance,
-    device: &Device,
-    data: &mut AppData
-) -> Result<(), MyError>
-{
-    let (color_image, color_image_memory) = create_image(
-        instance,
-        device,
-        data,
-        data.swapchain_extent.width,
-        data.swapchain_extent.height,
-        1,
-        data.msaa_samples,
-        data.swapchain_format,
-        vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
-    )?;
-
-    data.color_image = color_image;
-    data.color_image_memory = color_image_memory;
-
-    // Image View
-
-    data.color_image_view = create_image_view(
-        device,
-        data.color_image,
-        data.swapchain_format,
-        vk::ImageViewType::_2D,
-        vk::ImageAspectFlags::COLOR,
-        1,
-    )?;
-
-    Ok(())
-}
-
-unsafe fn create_depth_objects(
-    instance: &Instance,
-    device: &Device,
-    data: &mut AppData
-) -> Result<(), MyError>
-{
-    let format = get_depth_format(instance, data)?;
-    
-    let (depth_image, depth_image_memory) = create_image(
-        instance, 
-        device, 
-        data, 
-        data.swapchain_extent.width, 
-        data.swapchain_extent.height,
-        1,
-        data.msaa_samples,
-        format, 
-        vk::ImageTiling::OPTIMAL, 
-        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, 
-        vk::MemoryPropertyFlags::DEVICE_LOCAL
-    )?;
-    
-    data.depth_image = depth_image;
-    data.depth_image_memory = depth_image_memory;
-    
-    data.depth_image_view = create_image_view(
-        device, 
-        data.depth_image, 
-        format, 
-        vk::ImageViewType::_2D, 
-        vk::ImageAspectFlags::DEPTH,
-        1,
-    )?;
-
-    Ok(())
-}
-
 unsafe fn get_depth_format(instance: &Instance, data: &AppData) -> Result<vk::Format, MyError>
 {
     let canditates = &[
@@ -1611,75 +2525,133 @@ unsafe fn get_supported_format(
     }
 }
 
-unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Result<(), MyError>
+/// Records the passes of the render graph for `image_index`, the scene pass draws
+/// the scene followed by `draw`. `screenshot` copies the finished image.
+unsafe fn record_command_buffer<F>(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    screenshot: Option<&Screenshot>,
+    draw: F,
+) -> Result<(), MyError>
+where
+    F: FnOnce(&mut Frame) -> Result<(), MyError>,
 {
-    let allocate_info = vk::CommandBufferAllocateInfo::builder()
-        .command_pool(data.command_pool)
-        .level(vk::CommandBufferLevel::PRIMARY)
-        .command_buffer_count(data.framebuffers.len() as u32);
-    
-    data.command_buffers = device.allocate_command_buffers(&allocate_info)?;
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
     
-    for (i, command_buffer) in data.command_buffers.iter().enumerate() {
-        let inheritance = vk::CommandBufferInheritanceInfo::builder();
-        
-        let info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::empty())
-            .inheritance_info(&inheritance);
-        
-        device.begin_command_buffer(*command_buffer, &info)?;
-        
-        let render_area = vk::Rect2D::builder()
-            .offset(vk::Offset2D::default())
-            .extent(data.swapchain_extent);
-        
-        let color_clear_value = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.1, 0.1, 0.1, 1.0]
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    let mut draw = Some(draw);
+    data.render_graph.execute(device, command_buffer, image_index, |pass| {
+        if pass == data.scene_pass {
+            if let Some(draw) = draw.take() {
+                record_scene(device, data, command_buffer, image_index, draw)?;
             }
-        };
-        let depth_clear_value = vk::ClearValue {
-            depth_stencil: vk::ClearDepthStencilValue {
-                depth: 1.0,
-                stencil: 0,
-            },
-        };
-        
-        let clear_values = &[color_clear_value, depth_clear_value];
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(data.render_pass)
-            .framebuffer(data.framebuffers[i])
-            .render_area(render_area)
-            .clear_values(clear_values);
-        
-        device.cmd_begin_render_pass(*command_buffer, &info, vk::SubpassContents::INLINE);
-        
-        device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, data.pipeline);
-        
-        device.cmd_bind_vertex_buffers(
-            *command_buffer,
-            0,
-            &[data.vertex_buffer],
-            &[0]
-        );
+        }
+
+        Ok(())
+    })?;
+
+    if let Some(screenshot) = screenshot {
+        screenshot.record(device, data, command_buffer, image_index)?;
+    }
+    
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(())
+}
+
+unsafe fn record_scene<F>(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    draw: F,
+) -> Result<(), MyError>
+where
+    F: FnOnce(&mut Frame) -> Result<(), MyError>,
+{
+    device.cmd_bind_descriptor_sets(
+        command_buffer, 
+        vk::PipelineBindPoint::GRAPHICS, 
+        data.pipeline_layout,
+        0, 
+        &[data.descriptor_sets[image_index]],
+        &[]
+    );
+
+    let mut frame = Frame::new(device, data, command_buffer);
+
+    frame.bind_default_pipeline();
+    frame.set_viewport(0.0, 0.0, data.swapchain_extent.width as f32, data.swapchain_extent.height as f32);
+    frame.set_scissor(0, 0, data.swapchain_extent.width, data.swapchain_extent.height);
+
+    for instance in data.scene.get_instances() {
+        frame.draw(instance)?;
+    }
+
+    draw(&mut frame)
+}
+
+unsafe fn record_mesh_instance(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    instance: &MeshInstance,
+) -> Result<(), MyError>
+{
+    let mesh = data.meshes
+        .get(instance.mesh)
+        .ok_or_else(|| format!("Scene references unknown mesh {}!", instance.mesh))?;
+
+    // Still uploading, drawn once it finished
+    if transfer::is_pending(data, UploadTarget::Mesh(mesh.vertex_buffer)) {
+        return Ok(());
+    }
+
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::VERTEX,
+        0,
+        bytemuck::cast_slice(instance.transform.as_slice()),
+    );
+    device.cmd_bind_vertex_buffers(
+        command_buffer,
+        0,
+        &[mesh.vertex_buffer],
+        &[0]
+    );
+    device.cmd_bind_index_buffer(
+        command_buffer,
+        mesh.index_buffer, 
+        0, 
+        vk::IndexType::UINT32
+    );
+
+    for sub_mesh in &mesh.sub_meshes {
+        let texture_id = instance.texture
+            .or(sub_mesh.texture)
+            .unwrap_or(DEFAULT_TEXTURE);
+        let texture = data.textures
+            .get(texture_id)
+            .ok_or_else(|| format!("Scene references unknown texture {}!", texture_id))?;
+
+        if transfer::is_pending(data, UploadTarget::Texture(texture.image)) {
+            continue;
+        }
+
         device.cmd_bind_descriptor_sets(
-            *command_buffer, 
+            command_buffer, 
             vk::PipelineBindPoint::GRAPHICS, 
             data.pipeline_layout,
-            0, 
-            &[data.descriptor_sets[i]],
+            1, 
+            &[texture.descriptor_set],
             &[]
         );
-        device.cmd_bind_index_buffer(
-            *command_buffer,
-            data.index_buffer, 
-            0, 
-            vk::IndexType::UINT32
-        );
-        device.cmd_draw_indexed(*command_buffer, data.indices.len() as u32, 1, 0, 0, 0);
-        device.cmd_end_render_pass(*command_buffer);
-        
-        device.end_command_buffer(*command_buffer)?;
+        device.cmd_draw_indexed(command_buffer, sub_mesh.index_count, 1, sub_mesh.first_index, 0, 0);
     }
 
     Ok(())
@@ -1738,11 +2710,51 @@ unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result<(),
         
         data.in_flight_fences.push(device.create_fence(&fence_info, None)?);
     }
-    
-    data.images_in_flight = data.swapchain_images
-        .iter()
-        .map(|_| vk::Fence::null())
-        .collect();
+    
+    data.images_in_flight = data.swapchain_images
+        .iter()
+        .map(|_| vk::Fence::null())
+        .collect();
+
+    Ok(())
+}
+
+/// Compiles the GLSL sources when a compiler is installed, otherwise falls back
+/// to the SPIR-V embedded at build time. Returns a watcher for hot-reloading
+/// when enabled.
+fn load_shaders(config: &AppConfig, data: &mut AppData) -> Result<Option<ShaderWatcher>, MyError> {
+    if !shaders::is_compiler_available() {
+        warn!("No GLSL compiler found, using the embedded SPIR-V shaders");
+
+        data.vertex_shader = include_bytes!("../assets/shaders/compiled/vertex.spv").to_vec();
+        data.fragment_shader = include_bytes!("../assets/shaders/compiled/fragment.spv").to_vec();
+        data.compute_shader = include_bytes!("../assets/shaders/compiled/compute.spv").to_vec();
+
+        return Ok(None);
+    }
+
+    compile_shaders(config, data)?;
+
+    if !config.shader_hot_reload {
+        return Ok(None);
+    }
+
+    Ok(Some(ShaderWatcher::new(vec![
+        config.get_shader_path(VERTEX_SHADER)?,
+        config.get_shader_path(FRAGMENT_SHADER)?,
+        config.get_shader_path(COMPUTE_SHADER)?,
+    ])))
+}
+
+/// Only replaces the SPIR-V in `data` once every shader compiled.
+fn compile_shaders(config: &AppConfig, data: &mut AppData) -> Result<(), MyError> {
+    let vertex = shaders::compile_shader(&config.get_shader_path(VERTEX_SHADER)?, ShaderStage::Vertex)?;
+    let fragment = shaders::compile_shader(&config.get_shader_path(FRAGMENT_SHADER)?, ShaderStage::Fragment)?;
+    let compute = shaders::compile_shader(&config.get_shader_path(COMPUTE_SHADER)?, ShaderStage::Compute)?;
+
+    data.vertex_shader = vertex;
+    data.fragment_shader = fragment;
+    data.compute_shader = compute;
 
     Ok(())
 }
@@ -1761,103 +2773,110 @@ unsafe fn create_shader_module(
     Ok(device.create_shader_module(&info, None)?)
 }
 
-unsafe fn create_vertex_buffer(
+unsafe fn create_mesh(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
-) -> Result<(), MyError>
+    vertices: &[Vertex],
+    indices: &[u32],
+) -> Result<Mesh, MyError>
 {
-    let size = (size_of::<Vertex>() * data.vertices.len()) as u64;
+    let mut upload = transfer::begin_upload(instance, device, data)?;
 
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance,
+    let (vertex_buffer, vertex_buffer_memory) = transfer::upload_buffer(
         device,
         data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        &mut upload,
+        vertices,
+        BufferUsage {
+            usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+            stage: vk::PipelineStageFlags::VERTEX_INPUT,
+            access: vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        },
     )?;
-
-    // Copy (staging)
-
-    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
-
-    memcpy(data.vertices.as_ptr(), memory.cast(), data.vertices.len());
-
-    device.unmap_memory(staging_buffer_memory);
-
-    // Create (vertex)
-
-    let (vertex_buffer, vertex_buffer_memory) = create_buffer(
-        instance,
+    let (index_buffer, index_buffer_memory) = transfer::upload_buffer(
         device,
         data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        &mut upload,
+        indices,
+        BufferUsage {
+            usage: vk::BufferUsageFlags::INDEX_BUFFER,
+            stage: vk::PipelineStageFlags::VERTEX_INPUT,
+            access: vk::AccessFlags::INDEX_READ,
+        },
     )?;
 
-    data.vertex_buffer = vertex_buffer;
-    data.vertex_buffer_memory = vertex_buffer_memory;
-
-    // Copy (vertex)
-
-    copy_buffer(device, data, staging_buffer, vertex_buffer, size)?;
-
-    // Cleanup
-
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
-
-    Ok(())
+    transfer::submit_upload(device, data, upload, UploadTarget::Mesh(vertex_buffer))?;
+
+    Ok(Mesh {
+        vertex_buffer,
+        vertex_buffer_memory,
+        index_buffer,
+        index_buffer_memory,
+        sub_meshes: vec![SubMesh {
+            first_index: 0,
+            index_count: indices.len() as u32,
+            texture: None,
+        }],
+    })
 }
 
-unsafe fn create_index_buffer(
+/// Uploads the OBJ at `path` with one sub-mesh per material, loading each
+/// `diffuse_texture` relative to the OBJ.
+unsafe fn create_obj_mesh(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
-) -> Result<(), MyError>
+    path: &Path,
+) -> Result<Mesh, MyError>
 {
-    let size = (size_of::<u32>() * data.indices.len()) as u64;
-    
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance,
-        device,
-        data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE
-    )?;
-    
-    let memory = device.map_memory(
-        staging_buffer_memory,
-        0,
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
-    
-    memcpy(data.indices.as_ptr(), memory.cast(), data.indices.len());
+    let model = load_model(path)?;
 
-    device.unmap_memory(staging_buffer_memory);
-    
-    let (index_buffer, index_buffer_memory) = create_buffer(
-        instance,
-        device,
-        data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
-        vk::MemoryPropertyFlags::DEVICE_LOCAL
-    )?;
+    let mut mesh = create_mesh(instance, device, data, &model.vertices, &model.indices)?;
+    mesh.sub_meshes.clear();
 
-    data.index_buffer = index_buffer;
-    data.index_buffer_memory = index_buffer_memory;
+    for sub_mesh in &model.sub_meshes {
+        let texture = match &sub_mesh.diffuse_texture {
+            Some(texture_path) if !texture_path.is_file() => {
+                warn!("Missing diffuse texture {}, using white instead", texture_path.display());
+                Some(get_white_texture(instance, device, data)?)
+            },
+            Some(texture_path) => match data.obj_textures.get(texture_path) {
+                Some(texture) => Some(*texture),
+                None => {
+                    let texture = create_texture(instance, device, data, texture_path)?;
+                    data.textures.push(texture);
+                    data.obj_textures.insert(texture_path.clone(), data.textures.len() - 1);
+                    Some(data.textures.len() - 1)
+                },
+            },
+            None if sub_mesh.has_material => Some(get_white_texture(instance, device, data)?),
+            None => None,
+        };
 
-    copy_buffer(device, data, staging_buffer, index_buffer, size)?;
-    
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
-        
-    Ok(())
+        mesh.sub_meshes.push(SubMesh {
+            first_index: sub_mesh.first_index,
+            index_count: sub_mesh.index_count,
+            texture,
+        });
+    }
+
+    info!(
+        "Loaded mesh {} ({} vertices, {} indices, {} sub-meshes)",
+        path.display(),
+        model.vertices.len(),
+        model.indices.len(),
+        mesh.sub_meshes.len()
+    );
+
+    Ok(mesh)
+}
+
+unsafe fn destroy_mesh(device: &Device, allocator: &mut Allocator, mesh: &Mesh) {
+    device.destroy_buffer(mesh.vertex_buffer, None);
+    allocator.free(device, mesh.vertex_buffer_memory);
+    device.destroy_buffer(mesh.index_buffer, None);
+    allocator.free(device, mesh.index_buffer_memory);
 }
 
 unsafe fn create_uniform_buffers(
@@ -1866,57 +2885,28 @@ unsafe fn create_uniform_buffers(
     data: &mut AppData,
 ) -> Result<(), MyError>
 {
-    data.uniform_buffers.clear();
-    data.uniform_buffers_memory.clear();
-    
-    for _ in 0..data.swapchain_images.len() {
-        let (uniform_buffer, uniform_buffer_memory) = create_buffer(
-            instance,
-            device,
-            data,
-            size_of::<UniformBufferObject>() as u64,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE  
-        )?;
-        
-        data.uniform_buffers.push(uniform_buffer);
-        data.uniform_buffers_memory.push(uniform_buffer_memory);
-    }
+    let image_count = data.swapchain_images.len();
 
-    Ok(())
-}
+    data.uniform_ring = UniformRing::create(instance, device, data, image_count)?;
+    data.frame_uniforms.clear();
 
-unsafe fn get_memory_type_index(
-    instance: &Instance,
-    data: &AppData,
-    properties: vk::MemoryPropertyFlags,
-    requirements: vk::MemoryRequirements,
-) -> Result<u32, MyError>
-{
-    let memory = instance. get_physical_device_memory_properties(data.physical_device);
-    
-    if let Some(result) = (0..memory.memory_type_count)
-        .find(|i| {
-            let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
-            let memory_type = memory.memory_types[*i as usize];
-            
-            suitable && memory_type.property_flags.contains(properties)
-        })
-    {
-        return Ok(result);
+    for i in 0..image_count {
+        let camera = data.uniform_ring.reserve(i)?;
+        let lights = data.uniform_ring.reserve(i)?;
+
+        data.frame_uniforms.push(FrameUniforms { camera, lights });
     }
 
-    Err("Failed to find suitable memory type!".into())
+    Ok(())
 }
 
 unsafe fn create_buffer(
-    instance: &Instance,
     device: &Device,
     data: &mut AppData,
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Buffer, vk::DeviceMemory), MyError>
+) -> Result<(vk::Buffer, Allocation), MyError>
 {
     let buffer_info = vk::BufferCreateInfo::builder()
         .size(size)
@@ -1927,50 +2917,36 @@ unsafe fn create_buffer(
     
     let requirements = device.get_buffer_memory_requirements(buffer);
     
-    let memory_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(
-            instance, 
-            data, 
-            properties, 
-            requirements
-        )?);
+    let buffer_memory = data.allocator.allocate(device, requirements, properties, true)?;
     
-    let buffer_memory = device.allocate_memory(&memory_info, None)?;
-    
-    device.bind_buffer_memory(buffer, buffer_memory, 0)?;
+    device.bind_buffer_memory(buffer, buffer_memory.memory, buffer_memory.offset)?;
     
     Ok((buffer, buffer_memory))
 }
 
-unsafe fn copy_buffer(
+unsafe fn read_offscreen_image(
     device: &Device,
-    data: &AppData,
-    source: vk::Buffer,
-    destination: vk::Buffer,
-    size: vk::DeviceSize,
-) -> Result<(), MyError>
+    data: &mut AppData,
+) -> Result<image::DynamicImage, MyError>
 {
-    let command_buffer = begin_single_time_commands(device, data)?;
-    
-    let regions = vk::BufferCopy::builder().size(size);
-    
-    device.cmd_copy_buffer(command_buffer, source, destination, &[regions]);
-    
-    end_single_time_commands(device, data, command_buffer)?;
-    
-    Ok(())
-}
+    let width = data.swapchain_extent.width;
+    let height = data.swapchain_extent.height;
+    let size = (width * height * output::get_bytes_per_pixel(data.swapchain_format)) as u64;
 
-unsafe fn copy_buffer_to_image(
-    device: &Device,
-    data: &AppData,
-    buffer: vk::Buffer,
-    image: vk::Image,
-    width: u32,
-    height: u32,
-) -> Result<(), MyError>
-{
+    // Create (staging)
+
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    // Copy (image)
+
+    // The render graph left the image in `TRANSFER_SRC_OPTIMAL` and its dependency
+    // on later transfers makes the color writes visible to the copy
     let command_buffer = begin_single_time_commands(device, data)?;
 
     let subresource = vk::ImageSubresourceLayers::builder()
@@ -1991,17 +2967,47 @@ unsafe fn copy_buffer_to_image(
             depth: 1,
         });
 
-    device.cmd_copy_buffer_to_image(
+    device.cmd_copy_image_to_buffer(
         command_buffer,
-        buffer,
-        image,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        data.offscreen_image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        staging_buffer,
         &[region],
     );
 
+    let buffer_barrier = vk::BufferMemoryBarrier::builder()
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(staging_buffer)
+        .offset(0)
+        .size(size)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[buffer_barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
     end_single_time_commands(device, data, command_buffer)?;
 
-    Ok(())
+    // Copy (staging)
+
+    let mut pixels = vec![0u8; size as usize];
+
+    memcpy(staging_buffer_memory.get_mapped()?, pixels.as_mut_ptr(), pixels.len());
+
+    // Cleanup
+
+    device.destroy_buffer(staging_buffer, None);
+    data.allocator.free(device, staging_buffer_memory);
+
+    output::to_image(data.swapchain_format, width, height, pixels)
 }
 
 unsafe fn create_descriptor_set_layout(
@@ -2015,17 +3021,28 @@ unsafe fn create_descriptor_set_layout(
         .descriptor_count(1)
         .stage_flags(vk::ShaderStageFlags::VERTEX);
 
-    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+    let lights_binding = vk::DescriptorSetLayoutBinding::builder()
         .binding(1)
-        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
         .descriptor_count(1)
         .stage_flags(vk::ShaderStageFlags::FRAGMENT);
 
-    let bindings = &[ubo_binding, sampler_binding];
+    let bindings = &[ubo_binding, lights_binding];
     let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
 
     data.descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
 
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[sampler_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.texture_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
     Ok(())
 }
 
@@ -2036,18 +3053,33 @@ unsafe fn create_descriptor_pool(
 {
     let ubo_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count(2 * data.swapchain_images.len() as u32);
+
+    let pool_sizes = &[ubo_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(data.swapchain_images.len() as u32);
+
+    data.descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    Ok(())
+}
 
+unsafe fn create_texture_descriptor_pool(
+    device: &Device,
+    data: &mut AppData
+) -> Result<(), MyError>
+{
     let sampler_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count(MAX_TEXTURES);
 
-    let pool_sizes = &[ubo_size, sampler_size];
+    let pool_sizes = &[sampler_size];
     let info = vk::DescriptorPoolCreateInfo::builder()
         .pool_sizes(pool_sizes)
-        .max_sets(data.swapchain_images.len() as u32);
+        .max_sets(MAX_TEXTURES);
 
-    data.descriptor_pool = device.create_descriptor_pool(&info, None)?;
+    data.texture_descriptor_pool = device.create_descriptor_pool(&info, None)?;
 
     Ok(())
 }
@@ -2069,10 +3101,12 @@ unsafe fn create_descriptor_sets(
     // Update
 
     for i in 0..data.swapchain_images.len() {
+        let frame = data.frame_uniforms[i];
+
         let info = vk::DescriptorBufferInfo::builder()
-            .buffer(data.uniform_buffers[i])
-            .offset(0)
-            .range(size_of::<UniformBufferObject>() as u64);
+            .buffer(frame.camera.buffer)
+            .offset(frame.camera.offset)
+            .range(frame.camera.get_range());
 
         let buffer_info = &[info];
         let ubo_write = vk::WriteDescriptorSet::builder()
@@ -2082,136 +3116,259 @@ unsafe fn create_descriptor_sets(
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
             .buffer_info(buffer_info);
 
-        let info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(data.texture_image_view)
-            .sampler(data.texture_sampler);
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(frame.lights.buffer)
+            .offset(frame.lights.offset)
+            .range(frame.lights.get_range());
 
-        let image_info = &[info];
-        let sampler_write = vk::WriteDescriptorSet::builder()
+        let lights_buffer_info = &[info];
+        let lights_write = vk::WriteDescriptorSet::builder()
             .dst_set(data.descriptor_sets[i])
             .dst_binding(1)
             .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(image_info);
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(lights_buffer_info);
 
-        device.update_descriptor_sets(&[ubo_write, sampler_write], &[] as &[vk::CopyDescriptorSet]);
+        device.update_descriptor_sets(&[ubo_write, lights_write], &[] as &[vk::CopyDescriptorSet]);
     }
 
     Ok(())
 }
 
-unsafe fn transition_image_layout(
+unsafe fn create_texture_descriptor_set(
     device: &Device,
     data: &AppData,
-    image: vk::Image,
-    format: vk::Format,
-    old_layout: vk::ImageLayout,
-    new_layout: vk::ImageLayout,
-    mip_levels: u32,
-) -> Result<(), MyError>
+    image_view: vk::ImageView,
+) -> Result<vk::DescriptorSet, MyError>
 {
-    let (
-        src_access_mask,
-        dst_access_mask,
-        src_stage_mask,
-        dst_stage_mask
-    ) = match (old_layout, new_layout) {
-        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
-            vk::AccessFlags::empty(),
-            vk::AccessFlags::TRANSFER_WRITE,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::TRANSFER
-        ),
-        (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
-            vk::AccessFlags::TRANSFER_WRITE,
-            vk::AccessFlags::SHADER_READ,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::FRAGMENT_SHADER
-        ),
-        _ => return Err("Unsuported image layout transition!".into()),
-    };
+    if data.textures.len() as u32 >= MAX_TEXTURES {
+        return Err(format!("Texture limit of {} reached!", MAX_TEXTURES).into());
+    }
 
-    let command_buffer = begin_single_time_commands(device, data)?;
+    // Allocate
 
-    let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
-        .base_mip_level(0)
-        .level_count(mip_levels)
-        .base_array_layer(0)
-        .layer_count(1);
+    let layouts = &[data.texture_descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.texture_descriptor_pool)
+        .set_layouts(layouts);
 
-    let barrier = vk::ImageMemoryBarrier::builder()
-        .old_layout(old_layout) 
-        .new_layout(new_layout)
-        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .image(image)
-        .subresource_range(subresource)
-        .src_access_mask(src_access_mask)
-        .dst_access_mask(dst_access_mask);
+    let descriptor_set = device.allocate_descriptor_sets(&info)?[0];
 
-    device.cmd_pipeline_barrier(
-        command_buffer, 
-        src_stage_mask, 
-        dst_stage_mask,
-        vk::DependencyFlags::empty(), 
-        &[] as &[vk::MemoryBarrier], 
-        &[] as &[vk::BufferMemoryBarrier], 
-        &[barrier]
-    );
+    // Update
 
-    end_single_time_commands(device, data, command_buffer)?;
-    
-    Ok(())
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(image_view)
+        .sampler(data.texture_sampler);
+
+    let image_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[sampler_write], &[] as &[vk::CopyDescriptorSet]);
+
+    Ok(descriptor_set)
+}
+
+struct ObjModel {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    sub_meshes: Vec<ObjSubMesh>,
 }
 
-fn load_model(data: &mut AppData) -> Result<(), MyError> {
-    let mut reader = BufReader::new(File::open("C:/users/renat/personal/learn_vk/learn_vk/assets/objects/viking_room.obj")?);
+struct ObjSubMesh {
+    first_index: u32,
+    index_count: u32,
+    has_material: bool,
+    diffuse_texture: Option<PathBuf>,
+}
 
-    let (models, _) = tobj::load_obj_buf(
-        &mut reader,
+fn load_model(path: &Path) -> Result<ObjModel, MyError> {
+    let (mut models, materials) = tobj::load_obj(
+        path,
         &tobj::LoadOptions {
             triangulate: true,
+            single_index: true,
             ..Default::default()
         },
-        |_| Ok(Default::default()),
     )?;
 
+    let materials = materials.unwrap_or_else(|e| {
+        warn!("Failed to load materials of {}: {}", path.display(), e);
+        Vec::new()
+    });
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    // Keep the faces of each material contiguous so they form one sub-mesh
+    models.sort_by_key(|m| m.mesh.material_id);
+
     // Vertices / Indices
 
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut sub_meshes: Vec<ObjSubMesh> = Vec::new();
+    let mut current_material = None;
     let mut unique_vertices = HashMap::new();
 
     for model in &models {
+        let material = model.mesh.material_id.and_then(|i| materials.get(i));
+
+        if sub_meshes.is_empty() || current_material != model.mesh.material_id {
+            current_material = model.mesh.material_id;
+            sub_meshes.push(ObjSubMesh {
+                first_index: indices.len() as u32,
+                index_count: 0,
+                has_material: material.is_some(),
+                diffuse_texture: material
+                    .filter(|m| !m.diffuse_texture.is_empty())
+                    .map(|m| base.join(&m.diffuse_texture)),
+            });
+        }
+
+        // Textured materials are not tinted by their diffuse color
+        let color = match material {
+            Some(m) if m.diffuse_texture.is_empty() => glm::vec3(m.diffuse[0], m.diffuse[1], m.diffuse[2]),
+            _ => glm::vec3(1.0, 1.0, 1.0),
+        };
+
         for index in &model.mesh.indices {
             let pos_offset = (3 * index) as usize;
             let tex_coord_offset = (2 * index) as usize;
 
-            let vertex = Vertex {
-                position: glm::vec3(
+            let tex_coord = if model.mesh.texcoords.is_empty() {
+                glm::vec2(0.0, 0.0)
+            } else {
+                glm::vec2(
+                    model.mesh.texcoords[tex_coord_offset],
+                    1.0 - model.mesh.texcoords[tex_coord_offset + 1],
+                )
+            };
+
+            // Missing normals are generated once the mesh is complete
+            let normal = if model.mesh.normals.is_empty() {
+                glm::vec3(0.0, 0.0, 0.0)
+            } else {
+                glm::vec3(
+                    model.mesh.normals[pos_offset],
+                    model.mesh.normals[pos_offset + 1],
+                    model.mesh.normals[pos_offset + 2],
+                )
+            };
+
+            let vertex = Vertex::new(
+                glm::vec3(
                     model.mesh.positions[pos_offset],
                     model.mesh.positions[pos_offset + 1],
                     model.mesh.positions[pos_offset + 2],
                 ),
-                color: glm::vec3(1.0, 1.0, 1.0),
-                tex_coord: glm::vec2(
-                    model.mesh.texcoords[tex_coord_offset],
-                    1.0 - model.mesh.texcoords[tex_coord_offset + 1],
-                ),
-            };
+                color,
+                tex_coord,
+                normal,
+            );
 
             if let Some(index) = unique_vertices.get(&vertex) {
-                data.indices.push(*index as u32);
+                indices.push(*index as u32);
             } else {
-                let index = data.vertices.len();
+                let index = vertices.len();
                 unique_vertices.insert(vertex, index);
-                data.vertices.push(vertex);
-                data.indices.push(index as u32);
+                vertices.push(vertex);
+                indices.push(index as u32);
             }
         }
+
+        if let Some(sub_mesh) = sub_meshes.last_mut() {
+            sub_mesh.index_count = indices.len() as u32 - sub_mesh.first_index;
+        }
     }
 
-    Ok(())
+    if models.iter().any(|m| m.mesh.normals.is_empty()) {
+        generate_normals(&mut vertices, &indices);
+    }
+    generate_tangents(&mut vertices, &indices);
+
+    Ok(ObjModel {
+        vertices,
+        indices,
+        sub_meshes,
+    })
+}
+
+/// Fills every zero normal with the area weighted average of its face normals.
+fn generate_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut normals = vec![glm::Vec3::zeros(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let normal = glm::cross(
+            &(vertices[b].position - vertices[a].position),
+            &(vertices[c].position - vertices[a].position)
+        );
+
+        normals[a] += normal;
+        normals[b] += normal;
+        normals[c] += normal;
+    }
+
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        if vertex.normal != glm::Vec3::zeros() {
+            continue;
+        }
+
+        vertex.normal = if normal.norm_squared() > 0.0 {
+            normal.normalize()
+        } else {
+            glm::vec3(0.0, 0.0, 1.0)
+        };
+    }
+}
+
+/// Computes per-vertex tangents from the UV layout, orthogonalised against the normal.
+fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![glm::Vec3::zeros(); vertices.len()];
+    let mut bitangents = vec![glm::Vec3::zeros(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+
+        let edge_1 = vertices[b].position - vertices[a].position;
+        let edge_2 = vertices[c].position - vertices[a].position;
+        let delta_1 = vertices[b].tex_coord - vertices[a].tex_coord;
+        let delta_2 = vertices[c].tex_coord - vertices[a].tex_coord;
+
+        let determinant = delta_1.x * delta_2.y - delta_2.x * delta_1.y;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+
+        let tangent = (edge_1 * delta_2.y - edge_2 * delta_1.y) / determinant;
+        let bitangent = (edge_2 * delta_1.x - edge_1 * delta_2.x) / determinant;
+
+        for i in [a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = vertex.normal;
+        let tangent = tangents[i] - normal * glm::dot(&normal, &tangents[i]);
+
+        let tangent = if tangent.norm_squared() > f32::EPSILON {
+            tangent.normalize()
+        } else {
+            // No usable UVs, any vector perpendicular to the normal will do
+            let axis = if normal.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+            glm::cross(&normal, &axis).normalize()
+        };
+
+        let sign = if glm::dot(&glm::cross(&normal, &tangent), &bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+
+        vertex.tangent = glm::vec4(tangent.x, tangent.y, tangent.z, sign);
+    }
 }
 
 extern "system" fn debug_callback(
@@ -0,0 +1,408 @@
+use std::ptr;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::MyError;
+
+/// Size of the blocks sub-allocated on heaps larger than 1 GiB, smaller heaps use
+/// an eighth of their size.
+const BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+const SMALL_HEAP_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Range of device memory backing one buffer or image.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: u64,
+    pub size: u64,
+    /// Start of the range when the memory is host visible, null otherwise.
+    mapped: *mut u8,
+    /// `None` for dedicated allocations.
+    block: Option<u64>,
+}
+impl Allocation {
+    /// Host pointer to the range, mapped for the allocation's whole lifetime. Writes
+    /// need a flush unless `HOST_COHERENT` memory was requested.
+    pub fn get_mapped(&self) -> Result<*mut u8, MyError> {
+        if self.mapped.is_null() {
+            return Err("Allocation is not host visible!".into());
+        }
+
+        Ok(self.mapped)
+    }
+}
+impl Default for Allocation {
+    fn default() -> Self {
+        Self {
+            memory: vk::DeviceMemory::null(),
+            offset: 0,
+            size: 0,
+            mapped: ptr::null_mut(),
+            block: None,
+        }
+    }
+}
+
+/// Device memory usage reported by [`App::get_memory_stats`](super::App::get_memory_stats).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryStats {
+    /// Live `vkAllocateMemory` objects, blocks plus dedicated allocations.
+    pub device_allocations: usize,
+    pub block_count: usize,
+    pub dedicated_count: usize,
+    /// Buffers and images placed in blocks.
+    pub sub_allocations: usize,
+    /// Bytes allocated from the device.
+    pub reserved_bytes: u64,
+    /// Bytes bound to live resources.
+    pub used_bytes: u64,
+    /// `1 - largest free range / free bytes` over every block. 0 when the free space
+    /// of each block is contiguous.
+    pub fragmentation: f32,
+}
+
+#[derive(Clone, Debug)]
+struct Block {
+    id: u64,
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    /// Buffers and optimal tiling images never share a block, so
+    /// `bufferImageGranularity` can be ignored.
+    linear: bool,
+    size: u64,
+    used: u64,
+    allocations: usize,
+    mapped: *mut u8,
+    /// `(offset, size)` sorted by offset, adjacent ranges are always merged.
+    free: Vec<(u64, u64)>,
+}
+impl Block {
+    /// First fit, returns the aligned offset.
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let (i, offset) = self.free.iter().enumerate().find_map(|(i, (start, length))| {
+            let offset = start.next_multiple_of(alignment);
+            (offset + size <= start + length).then_some((i, offset))
+        })?;
+
+        let (start, length) = self.free.remove(i);
+
+        // Alignment padding stays free
+        let mut index = i;
+        if offset > start {
+            self.free.insert(index, (start, offset - start));
+            index += 1;
+        }
+        if offset + size < start + length {
+            self.free.insert(index, (offset + size, start + length - offset - size));
+        }
+
+        self.used += size;
+        self.allocations += 1;
+
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: u64, size: u64) {
+        let i = self.free.partition_point(|(start, _)| *start < offset);
+        self.free.insert(i, (offset, size));
+
+        // Merge with the next range, then the previous one
+        if i + 1 < self.free.len() && offset + size == self.free[i + 1].0 {
+            self.free[i].1 += self.free.remove(i + 1).1;
+        }
+        if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == offset {
+            self.free[i - 1].1 += self.free.remove(i).1;
+        }
+
+        self.used -= size;
+        self.allocations -= 1;
+    }
+}
+
+/// Sub-allocates buffers and images from large device memory blocks, keeping
+/// `vkAllocateMemory` calls far below `maxMemoryAllocationCount`. Resources larger
+/// than half a block get a dedicated allocation.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Allocator {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    blocks: Vec<Block>,
+    next_block_id: u64,
+    /// `(memory, size)` of every dedicated allocation.
+    dedicated: Vec<(vk::DeviceMemory, u64)>,
+}
+impl Allocator {
+    pub unsafe fn new(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self {
+        Self {
+            memory_properties: instance.get_physical_device_memory_properties(physical_device),
+            ..Default::default()
+        }
+    }
+
+    /// `linear` is `true` for buffers and linear tiling images.
+    pub unsafe fn allocate(
+        &mut self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        linear: bool,
+    ) -> Result<Allocation, MyError>
+    {
+        let memory_type = self.get_memory_type_index(properties, requirements)?;
+        let block_size = self.get_block_size(memory_type);
+
+        // Dedicated
+
+        if requirements.size > block_size / 2 {
+            let (memory, mapped) = self.allocate_memory(device, memory_type, requirements.size)?;
+            self.dedicated.push((memory, requirements.size));
+
+            return Ok(Allocation {
+                memory,
+                offset: 0,
+                size: requirements.size,
+                mapped,
+                block: None,
+            });
+        }
+
+        // Existing block
+
+        let alignment = requirements.alignment.max(1);
+
+        for block in self.blocks.iter_mut().filter(|b| b.memory_type == memory_type && b.linear == linear) {
+            if let Some(offset) = block.allocate(requirements.size, alignment) {
+                return Ok(get_block_allocation(block, offset, requirements.size));
+            }
+        }
+
+        // New block
+
+        let (memory, mapped) = self.allocate_memory(device, memory_type, block_size)?;
+
+        let mut block = Block {
+            id: self.next_block_id,
+            memory,
+            memory_type,
+            linear,
+            size: block_size,
+            used: 0,
+            allocations: 0,
+            mapped,
+            free: vec![(0, block_size)],
+        };
+        self.next_block_id += 1;
+
+        let offset = block
+            .allocate(requirements.size, alignment)
+            .ok_or("Allocation does not fit in an empty memory block!")?;
+
+        let allocation = get_block_allocation(&block, offset, requirements.size);
+        self.blocks.push(block);
+
+        Ok(allocation)
+    }
+
+    /// Returns the range to its block, releasing the block once it's empty. Null
+    /// allocations are ignored.
+    pub unsafe fn free(&mut self, device: &Device, allocation: Allocation) {
+        if allocation.memory.is_null() {
+            return;
+        }
+
+        let Some(id) = allocation.block else {
+            device.free_memory(allocation.memory, None);
+            self.dedicated.retain(|(m, _)| *m != allocation.memory);
+            return;
+        };
+
+        if let Some(i) = self.blocks.iter().position(|b| b.id == id) {
+            self.blocks[i].free(allocation.offset, allocation.size);
+
+            if self.blocks[i].allocations == 0 {
+                device.free_memory(self.blocks.remove(i).memory, None);
+            }
+        }
+    }
+
+    pub fn get_stats(&self) -> MemoryStats {
+        let block_bytes = self.blocks.iter().map(|b| b.size).sum::<u64>();
+        let dedicated_bytes = self.dedicated.iter().map(|(_, s)| s).sum::<u64>();
+        let free_bytes = self.blocks.iter().map(|b| b.size - b.used).sum::<u64>();
+        let largest_free = self.blocks
+            .iter()
+            .flat_map(|b| b.free.iter().map(|(_, s)| *s))
+            .max()
+            .unwrap_or(0);
+
+        MemoryStats {
+            device_allocations: self.blocks.len() + self.dedicated.len(),
+            block_count: self.blocks.len(),
+            dedicated_count: self.dedicated.len(),
+            sub_allocations: self.blocks.iter().map(|b| b.allocations).sum(),
+            reserved_bytes: block_bytes + dedicated_bytes,
+            used_bytes: block_bytes - free_bytes + dedicated_bytes,
+            fragmentation: if free_bytes == 0 { 0.0 } else { 1.0 - largest_free as f32 / free_bytes as f32 },
+        }
+    }
+
+    /// Frees every block and dedicated allocation, whether or not it's still in use.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.blocks.drain(..).for_each(|b| device.free_memory(b.memory, None));
+        self.dedicated.drain(..).for_each(|(m, _)| device.free_memory(m, None));
+    }
+
+    fn get_memory_type_index(
+        &self,
+        properties: vk::MemoryPropertyFlags,
+        requirements: vk::MemoryRequirements,
+    ) -> Result<u32, MyError>
+    {
+        (0..self.memory_properties.memory_type_count)
+            .find(|i| {
+                let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
+                let memory_type = self.memory_properties.memory_types[*i as usize];
+
+                suitable && memory_type.property_flags.contains(properties)
+            })
+            .ok_or_else(|| "Failed to find suitable memory type!".into())
+    }
+
+    fn get_block_size(&self, memory_type: u32) -> u64 {
+        let heap = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap as usize].size;
+
+        if heap_size <= SMALL_HEAP_SIZE { heap_size / 8 } else { BLOCK_SIZE }
+    }
+
+    /// Host visible memory is mapped for its whole lifetime.
+    unsafe fn allocate_memory(
+        &self,
+        device: &Device,
+        memory_type: u32,
+        size: u64,
+    ) -> Result<(vk::DeviceMemory, *mut u8), MyError>
+    {
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+
+        let memory = device.allocate_memory(&info, None)?;
+
+        let host_visible = self.memory_properties.memory_types[memory_type as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+
+        let mapped = if host_visible {
+            device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())?.cast()
+        } else {
+            ptr::null_mut()
+        };
+
+        Ok((memory, mapped))
+    }
+}
+
+fn get_block_allocation(block: &Block, offset: u64, size: u64) -> Allocation {
+    Allocation {
+        memory: block.memory,
+        offset,
+        size,
+        mapped: if block.mapped.is_null() { ptr::null_mut() } else { block.mapped.wrapping_add(offset as usize) },
+        block: Some(block.id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_block(size: u64) -> Block {
+        Block {
+            id: 0,
+            memory: vk::DeviceMemory::null(),
+            memory_type: 0,
+            linear: true,
+            size,
+            used: 0,
+            allocations: 0,
+            mapped: ptr::null_mut(),
+            free: vec![(0, size)],
+        }
+    }
+
+    #[test]
+    fn keeps_alignment_padding_free() {
+        let mut block = get_block(1024);
+
+        assert_eq!(block.allocate(10, 1), Some(0));
+        assert_eq!(block.allocate(100, 256), Some(256));
+        assert_eq!(block.free, vec![(10, 246), (356, 668)]);
+
+        // The padding is reused by allocations that fit
+        assert_eq!(block.allocate(200, 2), Some(10));
+        assert_eq!(block.free, vec![(210, 46), (356, 668)]);
+    }
+
+    #[test]
+    fn fails_without_a_large_enough_range() {
+        let mut block = get_block(1024);
+
+        assert_eq!(block.allocate(1000, 1), Some(0));
+        assert_eq!(block.allocate(16, 32), None);
+        assert_eq!(block.allocate(24, 1), Some(1000));
+        assert_eq!(block.allocate(1, 1), None);
+        assert!(block.free.is_empty());
+    }
+
+    #[test]
+    fn merges_freed_ranges_on_both_sides() {
+        let mut block = get_block(300);
+        let a = block.allocate(100, 1).unwrap();
+        let b = block.allocate(100, 1).unwrap();
+        let c = block.allocate(100, 1).unwrap();
+
+        // Neither neighbor is free
+        block.free(b, 100);
+        assert_eq!(block.free, vec![(100, 100)]);
+
+        // Merges with the next range
+        block.free(a, 100);
+        assert_eq!(block.free, vec![(0, 200)]);
+
+        // Merges with the previous range
+        block.free(c, 100);
+        assert_eq!(block.free, vec![(0, 300)]);
+    }
+
+    #[test]
+    fn merges_a_range_between_two_free_ones() {
+        let mut block = get_block(300);
+        let a = block.allocate(100, 1).unwrap();
+        let b = block.allocate(100, 1).unwrap();
+        let c = block.allocate(100, 1).unwrap();
+
+        block.free(a, 100);
+        block.free(c, 100);
+        assert_eq!(block.free, vec![(0, 100), (200, 100)]);
+
+        block.free(b, 100);
+        assert_eq!(block.free, vec![(0, 300)]);
+    }
+
+    #[test]
+    fn tracks_used_bytes_and_allocations() {
+        let mut block = get_block(1024);
+        let a = block.allocate(100, 1).unwrap();
+        let b = block.allocate(50, 64).unwrap();
+
+        // Padding doesn't count as used
+        assert_eq!((block.used, block.allocations), (150, 2));
+
+        block.free(a, 100);
+        assert_eq!((block.used, block.allocations), (50, 1));
+
+        block.free(b, 50);
+        assert_eq!((block.used, block.allocations), (0, 0));
+        assert_eq!(block.free, vec![(0, 1024)]);
+    }
+}
@@ -0,0 +1,176 @@
+use vulkanalia::prelude::v1_0::*;
+
+use crate::MyError;
+
+/// Layout transition of a range of mip levels, with access masks and stages
+/// derived from the layouts.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct ImageTransition {
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    base_mip_level: u32,
+    level_count: u32,
+    /// Replaces the derived `(src, dst)` stages.
+    stages: Option<(vk::PipelineStageFlags, vk::PipelineStageFlags)>,
+    /// `(src, dst)` families of an ownership transfer.
+    queue_families: (u32, u32),
+}
+impl ImageTransition {
+    /// Transitions the first mip level, the aspect follows `format`.
+    pub fn new(
+        image: vk::Image,
+        format: vk::Format,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> Self
+    {
+        Self {
+            image,
+            aspect_mask: get_aspect_mask(format),
+            old_layout,
+            new_layout,
+            base_mip_level: 0,
+            level_count: 1,
+            stages: None,
+            queue_families: (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+        }
+    }
+
+    pub fn with_mip_levels(mut self, base: u32, count: u32) -> Self {
+        self.base_mip_level = base;
+        self.level_count = count;
+        self
+    }
+    /// Needed on queues missing a derived stage, like the fragment stage on
+    /// compute-only queues.
+    pub fn with_stages(mut self, src: vk::PipelineStageFlags, dst: vk::PipelineStageFlags) -> Self {
+        self.stages = Some((src, dst));
+        self
+    }
+    /// Moves the image from the `src` to the `dst` queue family. Recorded once on
+    /// each queue, with the same layouts, to release and then acquire it.
+    pub fn with_queue_families(mut self, src: u32, dst: u32) -> Self {
+        self.queue_families = (src, dst);
+        self
+    }
+
+    /// Records the barrier into `command_buffer`.
+    pub unsafe fn record(&self, device: &Device, command_buffer: vk::CommandBuffer) -> Result<(), MyError> {
+        let (src_access_mask, src_stage_mask) = get_src_usage(self.old_layout)?;
+        let (dst_access_mask, dst_stage_mask) = get_dst_usage(self.new_layout)?;
+        let (src_stage_mask, dst_stage_mask) = self.stages.unwrap_or((src_stage_mask, dst_stage_mask));
+
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(self.aspect_mask)
+            .base_mip_level(self.base_mip_level)
+            .level_count(self.level_count)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(self.old_layout)
+            .new_layout(self.new_layout)
+            .src_queue_family_index(self.queue_families.0)
+            .dst_queue_family_index(self.queue_families.1)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage_mask,
+            dst_stage_mask,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier]
+        );
+
+        Ok(())
+    }
+}
+
+/// Depth and/or stencil for depth/stencil formats, color otherwise.
+pub(super) fn get_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM
+        | vk::Format::X8_D24_UNORM_PACK32
+        | vk::Format::D32_SFLOAT => vk::ImageAspectFlags::DEPTH,
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+/// Writes made in `layout` that the barrier makes available, and the stages
+/// making them.
+fn get_src_usage(layout: vk::ImageLayout) -> Result<(vk::AccessFlags, vk::PipelineStageFlags), MyError> {
+    Ok(match layout {
+        // Nothing to wait for, previous contents are discarded
+        vk::ImageLayout::UNDEFINED => (vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+        vk::ImageLayout::PREINITIALIZED => (vk::AccessFlags::HOST_WRITE, vk::PipelineStageFlags::HOST),
+        // Presentation is waited on with the acquire semaphore, ALL_COMMANDS chains
+        // with whatever stage it was waited at
+        vk::ImageLayout::PRESENT_SRC_KHR => (vk::AccessFlags::empty(), vk::PipelineStageFlags::ALL_COMMANDS),
+        _ => get_usage(layout)?,
+    })
+}
+
+/// Accesses in `layout` that wait on the barrier, and the stages making them.
+fn get_dst_usage(layout: vk::ImageLayout) -> Result<(vk::AccessFlags, vk::PipelineStageFlags), MyError> {
+    Ok(match layout {
+        vk::ImageLayout::UNDEFINED | vk::ImageLayout::PREINITIALIZED => {
+            return Err("Images can't be transitioned to an undefined or preinitialized layout!".into());
+        }
+        // Presentation waits on the render finished semaphore instead
+        vk::ImageLayout::PRESENT_SRC_KHR => (vk::AccessFlags::empty(), vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+        _ => get_usage(layout)?,
+    })
+}
+
+fn get_usage(layout: vk::ImageLayout) -> Result<(vk::AccessFlags, vk::PipelineStageFlags), MyError> {
+    let shader_stages = vk::PipelineStageFlags::VERTEX_SHADER
+        | vk::PipelineStageFlags::FRAGMENT_SHADER
+        | vk::PipelineStageFlags::COMPUTE_SHADER;
+    let fragment_tests = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+
+    Ok(match layout {
+        // Storage images
+        vk::ImageLayout::GENERAL => (
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            shader_stages
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            fragment_tests
+        ),
+        // Depth tested against while also sampled, like shadow maps
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+            fragment_tests | shader_stages
+        ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::AccessFlags::SHADER_READ,
+            shader_stages
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER
+        ),
+        _ => return Err(format!("Unsupported image layout {:?}!", layout).into()),
+    })
+}
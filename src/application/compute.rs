@@ -0,0 +1,412 @@
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use vulkanalia::prelude::v1_0::*;
+
+use super::{
+    barrier::ImageTransition, create_buffer, create_image_view, create_shader_module,
+    create_texture_descriptor_set, AppData, QueueFamilyIndices, Texture,
+};
+use crate::MyError;
+
+/// Matches `local_size_x` / `local_size_y` in `compute.glsl`.
+const WORKGROUP_SIZE: u32 = 8;
+const MAX_COMPUTE_TARGETS: u32 = 16;
+const COMPUTE_TARGET_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+pub(super) unsafe fn create_compute_descriptor_set_layout(
+    device: &Device,
+    data: &mut AppData
+) -> Result<(), MyError>
+{
+    let image_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let bindings = &[image_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.compute_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    Ok(())
+}
+
+pub(super) unsafe fn create_compute_descriptor_pool(
+    device: &Device,
+    data: &mut AppData
+) -> Result<(), MyError>
+{
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(MAX_COMPUTE_TARGETS);
+
+    let pool_sizes = &[image_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(MAX_COMPUTE_TARGETS);
+
+    data.compute_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    Ok(())
+}
+
+/// Creates the compute pipeline and its layout without replacing the current ones.
+pub(super) unsafe fn create_compute_pipeline(
+    device: &Device,
+    data: &AppData
+) -> Result<(vk::PipelineLayout, vk::Pipeline), MyError>
+{
+    let module = create_shader_module(device, &data.compute_shader)?;
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(module)
+        .name(b"main\0");
+
+    let set_layouts = &[data.compute_descriptor_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts);
+
+    let pipeline = device.create_pipeline_layout(&layout_info, None).and_then(|layout| {
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(layout);
+
+        match device.create_compute_pipelines(data.pipeline_cache, &[info], None) {
+            Ok((pipelines, _)) => Ok((layout, pipelines[0])),
+            Err(e) => {
+                device.destroy_pipeline_layout(layout, None);
+                Err(e)
+            }
+        }
+    });
+
+    device.destroy_shader_module(module, None);
+
+    Ok(pipeline?)
+}
+
+pub(super) unsafe fn create_compute_command_pool(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData
+) -> Result<(), MyError>
+{
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::empty())
+        .queue_family_index(indices.compute);
+
+    data.compute_command_pool = device.create_command_pool(&info, None)?;
+
+    Ok(())
+}
+
+/// Creates a storage image the compute shader writes into, sampled like any other
+/// [`Texture`]. Its contents are undefined until the first dispatch.
+pub(super) unsafe fn create_compute_target(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    width: u32,
+    height: u32,
+) -> Result<(Texture, vk::DescriptorSet), MyError>
+{
+    if data.compute_targets.len() as u32 >= MAX_COMPUTE_TARGETS {
+        return Err(format!("Compute target limit of {} reached!", MAX_COMPUTE_TARGETS).into());
+    }
+
+    // Create (image)
+
+    // Written on the compute queue and sampled on the graphics queue
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let queue_family_indices = &[indices.graphics, indices.compute];
+
+    let info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::_2D)
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .format(COMPUTE_TARGET_FORMAT)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(
+            vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+        )
+        .samples(vk::SampleCountFlags::_1);
+
+    let info = if indices.graphics == indices.compute {
+        info.sharing_mode(vk::SharingMode::EXCLUSIVE)
+    }
+    else {
+        info.sharing_mode(vk::SharingMode::CONCURRENT)
+            .queue_family_indices(queue_family_indices)
+    };
+
+    let image = device.create_image(&info, None)?;
+
+    // Memory
+
+    let requirements = device.get_image_memory_requirements(image);
+
+    let image_memory = data.allocator.allocate(
+        device,
+        requirements,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        false
+    )?;
+
+    device.bind_image_memory(image, image_memory.memory, image_memory.offset)?;
+
+    // Image View
+
+    let image_view = create_image_view(
+        device,
+        image,
+        COMPUTE_TARGET_FORMAT,
+        vk::ImageViewType::_2D,
+        vk::ImageAspectFlags::COLOR,
+        1
+    )?;
+
+    // Descriptors
+
+    let descriptor_set = create_texture_descriptor_set(device, data, image_view)?;
+
+    let layouts = &[data.compute_descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.compute_descriptor_pool)
+        .set_layouts(layouts);
+
+    let storage_descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::GENERAL)
+        .image_view(image_view);
+
+    let image_info = &[info];
+    let image_write = vk::WriteDescriptorSet::builder()
+        .dst_set(storage_descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[image_write], &[] as &[vk::CopyDescriptorSet]);
+
+    let texture = Texture {
+        image,
+        image_memory,
+        image_view,
+        mip_levels: 1,
+        width,
+        height,
+        descriptor_set,
+    };
+
+    Ok((texture, storage_descriptor_set))
+}
+
+/// Runs the compute shader over the whole `target` and waits for it to finish,
+/// leaving the image ready to be sampled. The device must be idle.
+pub(super) unsafe fn dispatch(
+    device: &Device,
+    data: &AppData,
+    target: &Texture,
+    storage_descriptor_set: vk::DescriptorSet,
+) -> Result<(), MyError>
+{
+    let command_buffer = begin_compute_commands(device, data)?;
+
+    // Previous contents are overwritten, so they can be discarded
+    ImageTransition::new(
+        target.image,
+        COMPUTE_TARGET_FORMAT,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::GENERAL,
+    )
+    .with_stages(vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::COMPUTE_SHADER)
+    .record(device, command_buffer)?;
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.compute_pipeline);
+
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        data.compute_pipeline_layout,
+        0,
+        &[storage_descriptor_set],
+        &[]
+    );
+
+    device.cmd_dispatch(
+        command_buffer,
+        target.width.div_ceil(WORKGROUP_SIZE),
+        target.height.div_ceil(WORKGROUP_SIZE),
+        1
+    );
+
+    // Compute-only queues have no fragment stage, ALL_COMMANDS covers it on the graphics queue
+    ImageTransition::new(
+        target.image,
+        COMPUTE_TARGET_FORMAT,
+        vk::ImageLayout::GENERAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    )
+    .with_stages(vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::ALL_COMMANDS)
+    .record(device, command_buffer)?;
+
+    end_compute_commands(device, data, command_buffer)
+}
+
+/// Copies a dispatched compute target back to the CPU. The device must be idle.
+pub(super) unsafe fn read_compute_target(
+    device: &Device,
+    data: &mut AppData,
+    target: &Texture,
+) -> Result<image::RgbaImage, MyError>
+{
+    let size = (target.width * target.height * 4) as u64;
+
+    // Create (staging)
+
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    // Copy (image)
+
+    let command_buffer = begin_compute_commands(device, data)?;
+
+    ImageTransition::new(
+        target.image,
+        COMPUTE_TARGET_FORMAT,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    )
+    .with_stages(vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::TRANSFER)
+    .record(device, command_buffer)?;
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D {
+            width: target.width,
+            height: target.height,
+            depth: 1,
+        });
+
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        target.image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        staging_buffer,
+        &[region],
+    );
+
+    ImageTransition::new(
+        target.image,
+        COMPUTE_TARGET_FORMAT,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    )
+    .with_stages(vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::ALL_COMMANDS)
+    .record(device, command_buffer)?;
+
+    let buffer_barrier = vk::BufferMemoryBarrier::builder()
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(staging_buffer)
+        .offset(0)
+        .size(size)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[buffer_barrier],
+        &[] as &[vk::ImageMemoryBarrier]
+    );
+
+    end_compute_commands(device, data, command_buffer)?;
+
+    // Copy (staging)
+
+    let mut pixels = vec![0u8; size as usize];
+
+    memcpy(staging_buffer_memory.get_mapped()?, pixels.as_mut_ptr(), pixels.len());
+
+    // Cleanup
+
+    device.destroy_buffer(staging_buffer, None);
+    data.allocator.free(device, staging_buffer_memory);
+
+    image::RgbaImage::from_raw(target.width, target.height, pixels)
+        .ok_or_else(|| "Compute target size does not match its pixel data!".into())
+}
+
+
+unsafe fn begin_compute_commands(
+    device: &Device,
+    data: &AppData
+) -> Result<vk::CommandBuffer, MyError>
+{
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(data.compute_command_pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    Ok(command_buffer)
+}
+
+unsafe fn end_compute_commands(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer
+) -> Result<(), MyError>
+{
+    device.end_command_buffer(command_buffer)?;
+
+    let command_buffers = &[command_buffer];
+    let info = vk::SubmitInfo::builder()
+        .command_buffers(command_buffers);
+
+    device.queue_submit(data.compute_queue, &[info], vk::Fence::null())?;
+    device.queue_wait_idle(data.compute_queue)?;
+
+    device.free_command_buffers(data.compute_command_pool, &[command_buffer]);
+
+    Ok(())
+}
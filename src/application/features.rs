@@ -0,0 +1,73 @@
+use vulkanalia::prelude::v1_0::*;
+
+use crate::MyError;
+
+/// Highest anisotropy requested from samplers, clamped to `maxSamplerAnisotropy`.
+const MAX_ANISOTROPY: f32 = 16.0;
+
+/// Name, whether a device supports it, and how to enable it.
+type Feature = (&'static str, fn(&vk::PhysicalDeviceFeatures) -> bool, fn(&mut vk::PhysicalDeviceFeatures));
+
+/// Features a device must support to be picked, always enabled. Everything the
+/// renderer uses has a fallback, so none are needed yet.
+const REQUIRED_FEATURES: &[Feature] = &[];
+
+/// Optional device features enabled on the logical device, returned by
+/// [`App::get_enabled_features`](super::App::get_enabled_features).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EnabledFeatures {
+    /// Anisotropy of the texture sampler, `None` when anisotropic filtering is
+    /// unsupported.
+    pub sampler_anisotropy: Option<f32>,
+    /// Shades multisampled pixels per sample to smooth textures inside triangles.
+    pub sample_rate_shading: bool,
+    pub texture_compression_bc: bool,
+    pub texture_compression_astc_ldr: bool,
+    pub texture_compression_etc2: bool,
+}
+impl EnabledFeatures {
+    /// Fails when `physical_device` misses a required feature, otherwise enables
+    /// every optional feature it supports.
+    pub(super) unsafe fn negotiate(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<Self, MyError>
+    {
+        let supported = instance.get_physical_device_features(physical_device);
+        let limits = instance.get_physical_device_properties(physical_device).limits;
+
+        let missing = REQUIRED_FEATURES
+            .iter()
+            .filter(|(_, supports, _)| !supports(&supported))
+            .map(|(name, _, _)| *name)
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            return Err(format!("Missing required features: {}!", missing.join(", ")).into());
+        }
+
+        Ok(Self {
+            sampler_anisotropy: (supported.sampler_anisotropy == vk::TRUE)
+                .then_some(MAX_ANISOTROPY.min(limits.max_sampler_anisotropy)),
+            sample_rate_shading: supported.sample_rate_shading == vk::TRUE,
+            texture_compression_bc: supported.texture_compression_bc == vk::TRUE,
+            texture_compression_astc_ldr: supported.texture_compression_astc_ldr == vk::TRUE,
+            texture_compression_etc2: supported.texture_compression_etc2 == vk::TRUE,
+        })
+    }
+
+    /// Features to create the logical device with.
+    pub(super) fn get_device_features(&self) -> vk::PhysicalDeviceFeatures {
+        let mut features = vk::PhysicalDeviceFeatures::builder()
+            .sampler_anisotropy(self.sampler_anisotropy.is_some())
+            .sample_rate_shading(self.sample_rate_shading)
+            .texture_compression_bc(self.texture_compression_bc)
+            .texture_compression_astc_ldr(self.texture_compression_astc_ldr)
+            .texture_compression_etc2(self.texture_compression_etc2)
+            .build();
+
+        REQUIRED_FEATURES.iter().for_each(|(_, _, enable)| enable(&mut features));
+
+        features
+    }
+}
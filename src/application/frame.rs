@@ -0,0 +1,89 @@
+use vulkanalia::prelude::v1_0::*;
+
+use super::{record_mesh_instance, AppData};
+use crate::{scene::MeshInstance, MyError};
+
+/// Command buffer of the frame being recorded, inside the render pass. Handed to
+/// the closure of [`App::render_with`](super::App::render_with) after the scene
+/// instances were drawn, with the default pipeline, descriptor sets, viewport and
+/// scissor bound.
+pub struct Frame<'a> {
+    device: &'a Device,
+    data: &'a AppData,
+    command_buffer: vk::CommandBuffer,
+}
+impl<'a> Frame<'a> {
+    pub(super) fn new(device: &'a Device, data: &'a AppData, command_buffer: vk::CommandBuffer) -> Self {
+        Self { device, data, command_buffer }
+    }
+
+    /// Draws a mesh instance that doesn't have to be part of the scene.
+    ///
+    /// # Safety
+    ///
+    /// The render pass must still be active, so only call this from the closure
+    /// of [`App::render_with`](super::App::render_with). The bound pipeline must be
+    /// compatible with [`Frame::get_pipeline_layout`].
+    pub unsafe fn draw(&mut self, instance: &MeshInstance) -> Result<(), MyError> {
+        record_mesh_instance(self.device, self.data, self.command_buffer, instance)
+    }
+
+    /// Binds `pipeline`, which must be compatible with the render pass and
+    /// [`Frame::get_pipeline_layout`]. Viewport and scissor must be dynamic.
+    ///
+    /// # Safety
+    ///
+    /// The render pass must still be active and `pipeline` must be a valid
+    /// graphics pipeline created for it with a layout compatible with
+    /// [`Frame::get_pipeline_layout`].
+    pub unsafe fn bind_pipeline(&mut self, pipeline: vk::Pipeline) {
+        self.device.cmd_bind_pipeline(self.command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    }
+    /// Rebinds the pipeline built from `vertex.glsl` and `fragment.glsl`.
+    ///
+    /// # Safety
+    ///
+    /// The render pass must still be active.
+    pub unsafe fn bind_default_pipeline(&mut self) {
+        self.bind_pipeline(self.data.pipeline);
+    }
+
+    /// # Safety
+    ///
+    /// The render pass must still be active and the bound pipeline must have a
+    /// dynamic viewport.
+    pub unsafe fn set_viewport(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        let viewport = vk::Viewport::builder()
+            .x(x)
+            .y(y)
+            .width(width)
+            .height(height)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        self.device.cmd_set_viewport(self.command_buffer, 0, &[viewport]);
+    }
+
+    /// # Safety
+    ///
+    /// The render pass must still be active and the bound pipeline must have a
+    /// dynamic scissor.
+    pub unsafe fn set_scissor(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D { x, y })
+            .extent(vk::Extent2D { width, height });
+
+        self.device.cmd_set_scissor(self.command_buffer, 0, &[scissor]);
+    }
+
+    pub fn get_extent(&self) -> (u32, u32) {
+        (self.data.swapchain_extent.width, self.data.swapchain_extent.height)
+    }
+    pub fn get_pipeline_layout(&self) -> vk::PipelineLayout {
+        self.data.pipeline_layout
+    }
+    /// For commands not covered by [`Frame`], they must be valid inside the render pass.
+    pub fn get_command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+}
@@ -0,0 +1,223 @@
+use std::path::Path;
+
+use nalgebra_glm as glm;
+use sllog::warn;
+
+use super::{generate_normals, generate_tangents, Vertex};
+use crate::{scene::SceneNode, MyError};
+
+pub(super) struct GltfPrimitive {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub base_color_image: Option<usize>,
+}
+
+pub(super) struct GltfImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+pub(super) struct GltfDocument {
+    pub primitives: Vec<GltfPrimitive>,
+    pub images: Vec<GltfImage>,
+    pub nodes: Vec<SceneNode>,
+    /// Primitives drawn by each node, indexed like `nodes`. Empty for nodes outside
+    /// the scene.
+    pub node_primitives: Vec<Vec<usize>>,
+    pub roots: Vec<usize>,
+}
+
+/// Reads a `.gltf` or `.glb` file, flattening every mesh into its triangle
+/// primitives and resolving the world transform of every node in the scene.
+pub(super) fn read_gltf(path: &Path) -> Result<GltfDocument, MyError> {
+    let (document, buffers, images) = gltf::import(path)?;
+
+    // Primitives
+
+    let mut primitives = Vec::new();
+    let mut mesh_primitives = Vec::new();
+
+    for mesh in document.meshes() {
+        let mut ids = Vec::new();
+
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                warn!("Skipping glTF primitive with mode {:?}", primitive.mode());
+                continue;
+            }
+
+            let primitive = read_primitive(&primitive, &buffers)?;
+            if primitive.vertices.is_empty() || primitive.indices.is_empty() {
+                warn!("Skipping empty glTF primitive in mesh {}", mesh.index());
+                continue;
+            }
+
+            ids.push(primitives.len());
+            primitives.push(primitive);
+        }
+
+        mesh_primitives.push(ids);
+    }
+
+    // Nodes
+
+    let mut nodes = document.nodes()
+        .map(|node| SceneNode {
+            name: node.name().map(String::from),
+            local_transform: to_mat4(node.transform().matrix()),
+            world_transform: glm::Mat4::identity(),
+            children: node.children().map(|c| c.index()).collect(),
+            instances: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+
+    let node_meshes = document.nodes()
+        .map(|node| node.mesh().map(|m| m.index()))
+        .collect::<Vec<_>>();
+
+    let roots = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene.nodes().map(|n| n.index()).collect::<Vec<_>>(),
+        None => (0..nodes.len())
+            .filter(|i| !nodes.iter().any(|n| n.children.contains(i)))
+            .collect(),
+    };
+
+    // Only nodes reachable from the roots are drawn
+    let mut node_primitives = vec![Vec::new(); nodes.len()];
+    let mut stack = roots
+        .iter()
+        .map(|r| (*r, glm::Mat4::identity()))
+        .collect::<Vec<_>>();
+
+    while let Some((index, parent_transform)) = stack.pop() {
+        let node = &mut nodes[index];
+        node.world_transform = parent_transform * node.local_transform;
+
+        if let Some(mesh) = node_meshes[index] {
+            node_primitives[index] = mesh_primitives[mesh].clone();
+        }
+
+        stack.extend(node.children.iter().map(|c| (*c, node.world_transform)));
+    }
+
+    // Images
+
+    let images = images
+        .into_iter()
+        .map(to_rgba8)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(GltfDocument {
+        primitives,
+        images,
+        nodes,
+        node_primitives,
+        roots,
+    })
+}
+
+fn read_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+) -> Result<GltfPrimitive, MyError>
+{
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let positions = reader
+        .read_positions()
+        .ok_or("glTF primitive has no positions!")?
+        .collect::<Vec<_>>();
+
+    let tex_coords = reader
+        .read_tex_coords(0)
+        .map(|t| t.into_f32().collect::<Vec<_>>())
+        .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
+
+    let normals = reader
+        .read_normals()
+        .map(|n| n.collect::<Vec<_>>());
+
+    let tangents = reader
+        .read_tangents()
+        .map(|t| t.collect::<Vec<_>>());
+
+    let colors = reader
+        .read_colors(0)
+        .map(|c| c.into_rgb_f32().collect::<Vec<_>>())
+        .unwrap_or_else(|| vec![[1.0, 1.0, 1.0]; positions.len()]);
+
+    let indices = reader
+        .read_indices()
+        .map(|i| i.into_u32().collect::<Vec<_>>())
+        .unwrap_or_else(|| (0..positions.len() as u32).collect());
+
+    let pbr = primitive.material().pbr_metallic_roughness();
+    let factor = pbr.base_color_factor();
+
+    // glTF UVs already have a top-left origin, unlike OBJ
+    let mut vertices = positions
+        .iter()
+        .zip(colors.iter())
+        .zip(tex_coords.iter())
+        .enumerate()
+        .map(|(i, ((position, color), tex_coord))| Vertex::new(
+            glm::vec3(position[0], position[1], position[2]),
+            glm::vec3(color[0] * factor[0], color[1] * factor[1], color[2] * factor[2]),
+            glm::vec2(tex_coord[0], tex_coord[1]),
+            normals
+                .as_ref()
+                .map(|n| glm::vec3(n[i][0], n[i][1], n[i][2]))
+                .unwrap_or_else(glm::Vec3::zeros),
+        ))
+        .collect::<Vec<_>>();
+
+    if normals.is_none() {
+        generate_normals(&mut vertices, &indices);
+    }
+
+    match tangents {
+        Some(tangents) => vertices
+            .iter_mut()
+            .zip(tangents)
+            .for_each(|(v, t)| v.tangent = glm::vec4(t[0], t[1], t[2], t[3])),
+        None => generate_tangents(&mut vertices, &indices),
+    }
+
+    Ok(GltfPrimitive {
+        vertices,
+        indices,
+        base_color_image: pbr.base_color_texture().map(|t| t.texture().source().index()),
+    })
+}
+
+fn to_rgba8(image: gltf::image::Data) -> Result<GltfImage, MyError> {
+    use gltf::image::Format;
+
+    let pixels = match image.format {
+        Format::R8G8B8A8 => image.pixels,
+        Format::R8G8B8 => image.pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        Format::R8G8 => image.pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[1], 0, 255])
+            .collect(),
+        Format::R8 => image.pixels
+            .iter()
+            .flat_map(|p| [*p, *p, *p, 255])
+            .collect(),
+        format => return Err(format!("Unsupported glTF image format {:?}!", format).into()),
+    };
+
+    Ok(GltfImage {
+        width: image.width,
+        height: image.height,
+        pixels,
+    })
+}
+
+fn to_mat4(columns: [[f32; 4]; 4]) -> glm::Mat4 {
+    glm::make_mat4(&columns.concat())
+}
@@ -0,0 +1,285 @@
+use std::{mem::size_of, slice};
+
+use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+use sllog::warn;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{config::OutputFormat, MyError};
+
+/// Luminance of SDR white on HDR outputs, from ITU-R BT.2408.
+const PAPER_WHITE_NITS: f32 = 203.0;
+/// Highlights above paper white roll off towards this luminance.
+const PEAK_NITS: f32 = 1000.0;
+/// Luminance of 1.0 in scRGB.
+const SCRGB_WHITE_NITS: f32 = 80.0;
+
+const SDR_FORMATS: &[(vk::Format, vk::ColorSpaceKHR)] = &[
+    (vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+    (vk::Format::R8G8B8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+];
+
+/// How the fragment shader encodes its linear output, mirrors the `ENCODING_*`
+/// constants in `fragment.glsl`.
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) enum OutputEncoding {
+    /// `*_SRGB` formats, encoded when stored.
+    #[default]
+    Hardware = 0,
+    /// UNORM and float formats presented as sRGB.
+    Srgb = 1,
+    /// Linear extended sRGB.
+    ScRgb = 2,
+    /// Rec. 2020 primaries with the PQ transfer function.
+    Pq = 3,
+}
+impl OutputEncoding {
+    pub fn get(format: vk::Format, color_space: vk::ColorSpaceKHR) -> Self {
+        match color_space {
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Self::ScRgb,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Self::Pq,
+            _ if is_srgb(format) => Self::Hardware,
+            _ => Self::Srgb,
+        }
+    }
+
+    /// Encodes a linear color the way `fragment.glsl` does, for clear values.
+    pub fn encode(self, color: [f32; 4]) -> [f32; 4] {
+        let [r, g, b, a] = color.map(|c| c.max(0.0));
+
+        let [r, g, b] = match self {
+            Self::Hardware => [r, g, b],
+            Self::Srgb => [r, g, b].map(|c| encode_srgb(c.min(1.0))),
+            Self::ScRgb => [r, g, b].map(|c| tonemap(c) * PAPER_WHITE_NITS / SCRGB_WHITE_NITS),
+            Self::Pq => {
+                let [r, g, b] = [r, g, b].map(tonemap);
+
+                [
+                    0.6274 * r + 0.3293 * g + 0.0433 * b,
+                    0.0691 * r + 0.9195 * g + 0.0114 * b,
+                    0.0164 * r + 0.0880 * g + 0.8956 * b,
+                ].map(|c| encode_pq(c * PAPER_WHITE_NITS))
+            }
+        };
+
+        [r, g, b, a]
+    }
+}
+
+/// Specialization constants of `fragment.glsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(super) struct OutputConstants {
+    encoding: i32,
+    paper_white_nits: f32,
+    peak_nits: f32,
+}
+impl OutputConstants {
+    pub fn new(encoding: OutputEncoding) -> Self {
+        Self {
+            encoding: encoding as i32,
+            paper_white_nits: PAPER_WHITE_NITS,
+            peak_nits: PEAK_NITS,
+        }
+    }
+
+    pub fn get_map_entries() -> [vk::SpecializationMapEntry; 3] {
+        let entry = |constant_id: u32, offset: usize| vk::SpecializationMapEntry {
+            constant_id,
+            offset: offset as u32,
+            size: 4,
+        };
+
+        [entry(0, 0), entry(1, 4), entry(2, 8)]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+}
+
+/// The first format of `output` the surface supports, falling back to SDR and
+/// then to whatever the surface prefers.
+pub(super) fn get_surface_format(
+    formats: &[vk::SurfaceFormatKHR],
+    output: OutputFormat,
+    colorspace_extension: bool,
+) -> vk::SurfaceFormatKHR
+{
+    let find = |candidates: &[(vk::Format, vk::ColorSpaceKHR)]| candidates
+        .iter()
+        .find_map(|(format, color_space)| formats
+            .iter()
+            .find(|f| f.format == *format && f.color_space == *color_space)
+            .cloned());
+
+    if output != OutputFormat::Sdr {
+        if needs_colorspace_extension(output) && !colorspace_extension {
+            warn!("{:?} output needs VK_EXT_swapchain_colorspace, using SDR", output);
+        }
+        else if let Some(format) = find(get_candidates(output)) {
+            return format;
+        }
+        else {
+            warn!("The surface doesn't support {:?} output, using SDR", output);
+        }
+    }
+
+    find(SDR_FORMATS).unwrap_or_else(|| formats[0])
+}
+
+/// Format of the offscreen image of headless apps, which don't depend on surface
+/// support.
+pub(super) fn get_offscreen_format(output: OutputFormat) -> (vk::Format, vk::ColorSpaceKHR) {
+    match output {
+        OutputFormat::Sdr => (vk::Format::R8G8B8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        _ => get_candidates(output)[0],
+    }
+}
+
+pub(super) fn get_bytes_per_pixel(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        _ => 4,
+    }
+}
+
+/// Converts `pixels` read back from an image of `format`. 8 bit formats become
+/// RGBA8 images, 10 and 16 bit ones RGBA16. Float pixels are encoded as sRGB and
+/// PQ pixels keep their encoding.
+pub(super) fn to_image(
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    mut pixels: Vec<u8>,
+) -> Result<DynamicImage, MyError>
+{
+    let rgba16 = match format {
+        vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => {
+            return rgba8(width, height, pixels);
+        }
+        vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => {
+            pixels.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+            return rgba8(width, height, pixels);
+        }
+        vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => {
+            let (r, b) = if format == vk::Format::A2B10G10R10_UNORM_PACK32 { (0, 20) } else { (20, 0) };
+
+            pixels
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    let v = u32::from_ne_bytes([p[0], p[1], p[2], p[3]]);
+                    let channel = |shift: u32| {
+                        let c = ((v >> shift) & 0x3ff) as u16;
+                        (c << 6) | (c >> 4)
+                    };
+
+                    [channel(r), channel(10), channel(b), (v >> 30) as u16 * 0x5555]
+                })
+                .collect::<Vec<_>>()
+        }
+        vk::Format::R16G16B16A16_SFLOAT => pixels
+            .chunks_exact(8)
+            .flat_map(|p| {
+                let channel = |i: usize| f16_to_f32(u16::from_ne_bytes([p[i * 2], p[i * 2 + 1]]));
+                let quantize = |c: f32| (c.clamp(0.0, 1.0) * 65535.0).round() as u16;
+
+                [
+                    quantize(encode_srgb(channel(0).clamp(0.0, 1.0))),
+                    quantize(encode_srgb(channel(1).clamp(0.0, 1.0))),
+                    quantize(encode_srgb(channel(2).clamp(0.0, 1.0))),
+                    quantize(channel(3)),
+                ]
+            })
+            .collect::<Vec<_>>(),
+        _ => return Err(format!("Reading back {:?} images is not supported!", format).into()),
+    };
+
+    ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, rgba16)
+        .map(DynamicImage::ImageRgba16)
+        .ok_or_else(|| "Image size does not match its pixel data!".into())
+}
+
+fn rgba8(width: u32, height: u32, pixels: Vec<u8>) -> Result<DynamicImage, MyError> {
+    RgbaImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| "Image size does not match its pixel data!".into())
+}
+
+/// Formats and color spaces `output` prefers, in order.
+fn get_candidates(output: OutputFormat) -> &'static [(vk::Format, vk::ColorSpaceKHR)] {
+    match output {
+        OutputFormat::Sdr => SDR_FORMATS,
+        OutputFormat::Sdr10 => &[
+            (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::A2R10G10B10_UNORM_PACK32, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        ],
+        OutputFormat::ExtendedSrgb => &[
+            (vk::Format::R16G16B16A16_SFLOAT, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT),
+        ],
+        OutputFormat::Hdr10 => &[
+            (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+            (vk::Format::A2R10G10B10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+        ],
+    }
+}
+
+fn needs_colorspace_extension(output: OutputFormat) -> bool {
+    matches!(output, OutputFormat::ExtendedSrgb | OutputFormat::Hdr10)
+}
+
+fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+/// Identity up to paper white, then rolls off towards the peak luminance.
+fn tonemap(c: f32) -> f32 {
+    let peak = PEAK_NITS / PAPER_WHITE_NITS;
+
+    if c <= 1.0 {
+        c
+    } else {
+        1.0 + (peak - 1.0) * (1.0 - (-(c - 1.0) / (peak - 1.0)).exp())
+    }
+}
+
+fn encode_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// SMPTE ST 2084 inverse EOTF of a luminance in nits.
+// The constants are the exact values of the rationals in ST 2084, which are all
+// representable in f32
+#[allow(clippy::excessive_precision)]
+fn encode_pq(nits: f32) -> f32 {
+    const M1: f32 = 0.1593017578125;
+    const M2: f32 = 78.84375;
+    const C1: f32 = 0.8359375;
+    const C2: f32 = 18.8515625;
+    const C3: f32 = 18.6875;
+
+    let y = (nits / 10000.0).clamp(0.0, 1.0).powf(M1);
+
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+
+    let magnitude = match exponent {
+        0 => mantissa as f32 / (1 << 24) as f32,
+        0x1f if mantissa == 0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => f32::from_bits(((exponent + 112) << 23) | (mantissa << 13)),
+    };
+
+    if bits & 0x8000 != 0 { -magnitude } else { magnitude }
+}
@@ -0,0 +1,189 @@
+use std::{fs, path::Path};
+
+use sllog::{info, warn};
+use vulkanalia::prelude::v1_0::*;
+
+use super::AppData;
+use crate::MyError;
+
+/// Size of `VkPipelineCacheHeaderVersionOne`.
+const HEADER_SIZE: usize = 32;
+
+/// Creates the cache shared by every pipeline, starting from the file at `path`
+/// when it was written for this device and driver.
+pub(super) unsafe fn create_pipeline_cache(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: Option<&Path>,
+) -> Result<(), MyError>
+{
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    let initial_data = path
+        .and_then(|p| load(p, &properties))
+        .unwrap_or_default();
+
+    // Create
+
+    let info = vk::PipelineCacheCreateInfo::builder()
+        .initial_data(&initial_data);
+
+    data.pipeline_cache = device.create_pipeline_cache(&info, None)?;
+
+    Ok(())
+}
+
+/// Writes the cache to `path` through a temporary file, so an interrupted write
+/// keeps the previous cache.
+pub(super) unsafe fn save_pipeline_cache(
+    device: &Device,
+    data: &AppData,
+    path: &Path,
+) -> Result<(), MyError>
+{
+    let bytes = device.get_pipeline_cache_data(data.pipeline_cache)?;
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, &bytes)?;
+    fs::rename(&temp_path, path)?;
+
+    info!("Saved pipeline cache {} ({} bytes)", path.display(), bytes.len());
+
+    Ok(())
+}
+
+/// Reads the cache at `path`, `None` when there is none yet or it doesn't match
+/// the device.
+fn load(path: &Path, properties: &vk::PhysicalDeviceProperties) -> Option<Vec<u8>> {
+    let bytes = fs::read(path).ok()?;
+
+    match validate(&bytes, properties) {
+        Ok(()) => {
+            info!("Loaded pipeline cache {} ({} bytes)", path.display(), bytes.len());
+            Some(bytes)
+        }
+        Err(reason) => {
+            warn!("Ignoring pipeline cache {}: {}", path.display(), reason);
+            None
+        }
+    }
+}
+
+/// Checks the header against the device. Drivers are supposed to reject foreign
+/// data themselves, but not all of them do it gracefully.
+fn validate(bytes: &[u8], properties: &vk::PhysicalDeviceProperties) -> Result<(), String> {
+    if bytes.len() < HEADER_SIZE {
+        return Err("truncated header".into());
+    }
+
+    // Header fields are little endian regardless of the host
+    let read_u32 = |offset: usize| u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ]);
+
+    let header_size = read_u32(0) as usize;
+
+    if header_size < HEADER_SIZE || header_size > bytes.len() {
+        Err(format!("invalid header size {}", header_size))
+    }
+    else if read_u32(4) != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+        Err(format!("unknown header version {}", read_u32(4)))
+    }
+    else if read_u32(8) != properties.vendor_id {
+        Err(format!("written for vendor {:#x}", read_u32(8)))
+    }
+    else if read_u32(12) != properties.device_id {
+        Err(format!("written for device {:#x}", read_u32(12)))
+    }
+    else if bytes[16..HEADER_SIZE] != properties.pipeline_cache_uuid[..] {
+        Err("written by another driver version".into())
+    }
+    else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_properties() -> vk::PhysicalDeviceProperties {
+        vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2684,
+            pipeline_cache_uuid: [7; 16].into(),
+            ..Default::default()
+        }
+    }
+
+    /// Header written by `properties`, followed by some driver data.
+    fn get_cache(properties: &vk::PhysicalDeviceProperties) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        bytes.extend_from_slice(&(vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_le_bytes());
+        bytes.extend_from_slice(&properties.vendor_id.to_le_bytes());
+        bytes.extend_from_slice(&properties.device_id.to_le_bytes());
+        bytes.extend_from_slice(&properties.pipeline_cache_uuid[..]);
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        bytes
+    }
+
+    #[test]
+    fn accepts_matching_header() {
+        let properties = get_properties();
+
+        assert_eq!(validate(&get_cache(&properties), &properties), Ok(()));
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let properties = get_properties();
+        let cache = get_cache(&properties);
+
+        assert!(validate(&cache[..HEADER_SIZE - 1], &properties).is_err());
+        assert!(validate(&[], &properties).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_header_size() {
+        let properties = get_properties();
+        let mut cache = get_cache(&properties);
+        cache[0..4].copy_from_slice(&1000u32.to_le_bytes());
+
+        assert!(validate(&cache, &properties).is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_vendor() {
+        let properties = get_properties();
+        let cache = get_cache(&vk::PhysicalDeviceProperties { vendor_id: 0x1002, ..properties });
+
+        assert!(validate(&cache, &properties).is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_device() {
+        let properties = get_properties();
+        let cache = get_cache(&vk::PhysicalDeviceProperties { device_id: 0x1234, ..properties });
+
+        assert!(validate(&cache, &properties).is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_uuid() {
+        let properties = get_properties();
+        let cache = get_cache(&vk::PhysicalDeviceProperties {
+            pipeline_cache_uuid: [8; 16].into(),
+            ..properties
+        });
+
+        assert!(validate(&cache, &properties).is_err());
+    }
+}
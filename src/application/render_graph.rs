@@ -0,0 +1,516 @@
+use vulkanalia::prelude::v1_0::*;
+
+use super::{
+    allocator::{Allocation, Allocator},
+    barrier::get_aspect_mask,
+    create_image, create_image_view, AppData,
+};
+use crate::MyError;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub(super) struct ResourceId(usize);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub(super) struct PassId(usize);
+
+/// What happens to an attachment's contents when a pass starts.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Load {
+    Clear(vk::ClearValue),
+    DontCare,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ImageSource {
+    /// Allocated and owned by the graph.
+    Transient { extent: vk::Extent2D },
+    /// The swapchain image being rendered, left in `final_layout` after its last use.
+    Swapchain { final_layout: vk::ImageLayout },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ImageDesc {
+    name: &'static str,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    source: ImageSource,
+}
+
+/// Images a pass renders to. Attachments are bound in the order colors, depth,
+/// resolves.
+#[derive(Clone, Debug, Default)]
+pub(super) struct PassDesc {
+    name: &'static str,
+    colors: Vec<(ResourceId, Load)>,
+    depth: Option<(ResourceId, Load)>,
+    /// Resolve targets of `colors`, in the same order.
+    resolves: Vec<ResourceId>,
+}
+impl PassDesc {
+    pub fn new(name: &'static str) -> Self {
+        Self { name, ..Default::default() }
+    }
+
+    pub fn with_color(mut self, image: ResourceId, load: Load) -> Self {
+        self.colors.push((image, load));
+        self
+    }
+    pub fn with_depth(mut self, image: ResourceId, load: Load) -> Self {
+        self.depth = Some((image, load));
+        self
+    }
+    pub fn with_resolve(mut self, image: ResourceId) -> Self {
+        self.resolves.push(image);
+        self
+    }
+
+    fn get_attachments(&self) -> Vec<(ResourceId, Load, Usage)> {
+        self.colors
+            .iter()
+            .map(|(r, l)| (*r, *l, Usage::Color))
+            .chain(self.depth.map(|(r, l)| (r, l, Usage::Depth)))
+            .chain(self.resolves.iter().map(|r| (*r, Load::DontCare, Usage::Color)))
+            .collect()
+    }
+
+    fn writes(&self, image: ResourceId) -> bool {
+        self.get_attachments().iter().any(|(r, _, _)| *r == image)
+    }
+
+    fn get_layout(&self, image: ResourceId) -> Option<vk::ImageLayout> {
+        self.get_attachments()
+            .iter()
+            .find(|(r, _, _)| *r == image)
+            .map(|(_, _, u)| u.get_layout())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Usage {
+    Color,
+    Depth,
+}
+impl Usage {
+    const fn get_layout(self) -> vk::ImageLayout {
+        match self {
+            Self::Color => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Self::Depth => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TransientImage {
+    image: vk::Image,
+    memory: Allocation,
+    view: vk::ImageView,
+}
+
+#[derive(Clone, Debug, Default)]
+struct CompiledPass {
+    render_pass: vk::RenderPass,
+    /// One per swapchain image.
+    framebuffers: Vec<vk::Framebuffer>,
+    extent: vk::Extent2D,
+    clear_values: Vec<vk::ClearValue>,
+}
+
+/// Frame made of render passes that declare the images they use. Compiling orders
+/// the passes, allocates transient images and creates a render pass per pass whose
+/// layouts and dependencies take each image from one use to the next.
+#[derive(Clone, Debug, Default)]
+pub(super) struct RenderGraph {
+    images: Vec<ImageDesc>,
+    passes: Vec<PassDesc>,
+    order: Vec<usize>,
+    transients: Vec<Option<TransientImage>>,
+    compiled: Vec<CompiledPass>,
+}
+impl RenderGraph {
+    pub fn add_transient(
+        &mut self,
+        name: &'static str,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        extent: vk::Extent2D,
+    ) -> ResourceId
+    {
+        self.add_image(ImageDesc { name, format, samples, source: ImageSource::Transient { extent } })
+    }
+
+    /// The swapchain image of the frame, or the offscreen image when headless.
+    pub fn import_swapchain(
+        &mut self,
+        name: &'static str,
+        format: vk::Format,
+        final_layout: vk::ImageLayout,
+    ) -> ResourceId
+    {
+        self.add_image(ImageDesc {
+            name,
+            format,
+            samples: vk::SampleCountFlags::_1,
+            source: ImageSource::Swapchain { final_layout },
+        })
+    }
+
+    pub fn add_pass(&mut self, pass: PassDesc) -> PassId {
+        self.passes.push(pass);
+        PassId(self.passes.len() - 1)
+    }
+
+    /// Creates everything the passes need. Whatever was created before a failure
+    /// is destroyed again.
+    pub unsafe fn compile(
+        &mut self,
+        device: &Device,
+        data: &mut AppData,
+    ) -> Result<(), MyError>
+    {
+        let result = self.create(device, data);
+
+        if result.is_err() {
+            self.destroy(device, &mut data.allocator);
+            self.transients.clear();
+            self.compiled.clear();
+        }
+
+        result
+    }
+
+    /// Records every pass in order, `record` fills the pass between the begin and
+    /// end of its render pass.
+    pub unsafe fn execute<F>(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        mut record: F,
+    ) -> Result<(), MyError>
+    where
+        F: FnMut(PassId) -> Result<(), MyError>,
+    {
+        for pass in &self.order {
+            let compiled = &self.compiled[*pass];
+
+            let render_area = vk::Rect2D::builder()
+                .offset(vk::Offset2D::default())
+                .extent(compiled.extent);
+
+            let info = vk::RenderPassBeginInfo::builder()
+                .render_pass(compiled.render_pass)
+                .framebuffer(compiled.framebuffers[image_index])
+                .render_area(render_area)
+                .clear_values(&compiled.clear_values);
+
+            device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+            record(PassId(*pass))?;
+
+            device.cmd_end_render_pass(command_buffer);
+        }
+
+        Ok(())
+    }
+
+    pub fn get_render_pass(&self, pass: PassId) -> vk::RenderPass {
+        self.compiled[pass.0].render_pass
+    }
+
+    #[rustfmt::skip]
+    pub unsafe fn destroy(&self, device: &Device, allocator: &mut Allocator) {
+        for compiled in &self.compiled {
+            compiled.framebuffers.iter().for_each(|f| device.destroy_framebuffer(*f, None));
+            device.destroy_render_pass(compiled.render_pass, None);
+        }
+
+        for transient in self.transients.iter().flatten() {
+            device.destroy_image_view(transient.view, None);
+            device.destroy_image(transient.image, None);
+            allocator.free(device, transient.memory);
+        }
+    }
+
+    fn add_image(&mut self, image: ImageDesc) -> ResourceId {
+        self.images.push(image);
+        ResourceId(self.images.len() - 1)
+    }
+
+    unsafe fn create(
+        &mut self,
+        device: &Device,
+        data: &mut AppData,
+    ) -> Result<(), MyError>
+    {
+        self.order = self.get_order()?;
+
+        for i in 0..self.images.len() {
+            let transient = self.create_transient(device, data, ResourceId(i))?;
+            self.transients.push(transient);
+        }
+
+        self.compiled = vec![CompiledPass::default(); self.passes.len()];
+        for (position, pass) in self.order.clone().into_iter().enumerate() {
+            self.compiled[pass] = self.compile_pass(device, data, pass, position)?;
+        }
+
+        Ok(())
+    }
+
+    /// Topological order of the passes, ties broken by the order they were added.
+    /// A pass using an image comes after earlier added passes writing it.
+    fn get_order(&self) -> Result<Vec<usize>, MyError> {
+        let count = self.passes.len();
+        let mut dependencies = vec![Vec::new(); count];
+
+        for (b, pass) in self.passes.iter().enumerate() {
+            for image in (0..self.images.len()).map(ResourceId) {
+                if pass.get_layout(image).is_some() {
+                    dependencies[b].extend((0..b).filter(|a| self.passes[*a].writes(image)));
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(count);
+        while order.len() < count {
+            let next = (0..count)
+                .find(|p| !order.contains(p) && dependencies[*p].iter().all(|d| order.contains(d)))
+                .ok_or("Render graph passes depend on each other in a cycle!")?;
+
+            order.push(next);
+        }
+
+        Ok(order)
+    }
+
+    unsafe fn create_transient(
+        &self,
+        device: &Device,
+        data: &mut AppData,
+        image: ResourceId,
+    ) -> Result<Option<TransientImage>, MyError>
+    {
+        let desc = self.images[image.0];
+        let ImageSource::Transient { extent } = desc.source else {
+            return Ok(None);
+        };
+
+        // Contents never leave a pass, so they don't need backing memory on tilers
+        let mut usage = vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+
+        for pass in &self.passes {
+            for (r, _, u) in pass.get_attachments() {
+                if r != image {
+                    continue;
+                }
+
+                if u == Usage::Depth {
+                    usage |= vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+                } else {
+                    usage |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
+                }
+            }
+        }
+
+        let (image, memory) = create_image(
+            device,
+            data,
+            extent.width,
+            extent.height,
+            1,
+            desc.samples,
+            desc.format,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let aspect = get_aspect_mask(desc.format);
+        let view = create_image_view(device, image, desc.format, vk::ImageViewType::_2D, aspect, 1)?;
+
+        Ok(Some(TransientImage { image, memory, view }))
+    }
+
+    /// Creates the render pass and framebuffers of the pass at `position` in the
+    /// order.
+    unsafe fn compile_pass(
+        &self,
+        device: &Device,
+        data: &AppData,
+        pass: usize,
+        position: usize,
+    ) -> Result<CompiledPass, MyError>
+    {
+        let desc = &self.passes[pass];
+        let attachments = desc.get_attachments();
+
+        if attachments.is_empty() {
+            return Err(format!("Pass {} has no attachments!", desc.name).into());
+        }
+
+        let get_extent = |image: ResourceId| match self.images[image.0].source {
+            ImageSource::Transient { extent } => extent,
+            ImageSource::Swapchain { .. } => data.swapchain_extent,
+        };
+
+        let extent = get_extent(attachments[0].0);
+        if let Some((image, _, _)) = attachments.iter().find(|(i, _, _)| get_extent(*i) != extent) {
+            return Err(format!(
+                "{} doesn't match the size of the other attachments of pass {}!",
+                self.images[image.0].name,
+                desc.name
+            ).into());
+        }
+
+        // Attachments
+
+        let mut descriptions = Vec::with_capacity(attachments.len());
+        let mut clear_values = Vec::with_capacity(attachments.len());
+
+        for (image, load, usage) in &attachments {
+            let image_desc = self.images[image.0];
+            let next_layout = self.order[position + 1..]
+                .iter()
+                .find_map(|p| self.passes[*p].get_layout(*image));
+
+            let final_layout = match (next_layout, image_desc.source) {
+                (Some(layout), _) => layout,
+                (None, ImageSource::Swapchain { final_layout }) => final_layout,
+                (None, ImageSource::Transient { .. }) => usage.get_layout(),
+            };
+
+            // Passes never load attachments, only the swapchain image is kept
+            let stored = matches!(image_desc.source, ImageSource::Swapchain { .. });
+
+            let load_op = match load {
+                Load::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+                Load::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+            };
+
+            descriptions.push(vk::AttachmentDescription::builder()
+                .format(image_desc.format)
+                .samples(image_desc.samples)
+                .load_op(load_op)
+                .store_op(if stored { vk::AttachmentStoreOp::STORE } else { vk::AttachmentStoreOp::DONT_CARE })
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(final_layout));
+
+            clear_values.push(match load {
+                Load::Clear(value) => *value,
+                _ => vk::ClearValue::default(),
+            });
+        }
+
+        // Subpasses
+
+        let references = attachments
+            .iter()
+            .enumerate()
+            .map(|(i, (_, _, usage))| vk::AttachmentReference::builder()
+                .attachment(i as u32)
+                .layout(usage.get_layout())
+                .build())
+            .collect::<Vec<_>>();
+
+        let color_count = desc.colors.len();
+        let depth_count = desc.depth.is_some() as usize;
+
+        if !desc.resolves.is_empty() && desc.resolves.len() != color_count {
+            return Err(format!("Pass {} must resolve every color attachment or none!", desc.name).into());
+        }
+
+        let color_attachments = &references[..color_count];
+        let resolve_attachments = &references[color_count + depth_count..];
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments);
+
+        let subpass = match desc.depth {
+            Some(_) => subpass.depth_stencil_attachment(&references[color_count]),
+            None => subpass,
+        };
+
+        let subpass = if resolve_attachments.is_empty() {
+            subpass
+        } else {
+            subpass.resolve_attachments(resolve_attachments)
+        };
+
+        // Dependencies
+
+        let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let attachment_writes = vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+
+        // Earlier passes (or frames) writing the attachments
+        let external_dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(attachment_stages)
+            .src_access_mask(attachment_writes)
+            .dst_stage_mask(attachment_stages)
+            .dst_access_mask(attachment_writes
+                | vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ);
+
+        // Copies after the graph, like screenshots
+        let read_dependency = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(attachment_stages)
+            .src_access_mask(attachment_writes)
+            .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+        // Create
+
+        let subpasses = &[subpass];
+        let dependencies = &[external_dependency, read_dependency];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(&descriptions)
+            .subpasses(subpasses)
+            .dependencies(dependencies);
+
+        let render_pass = device.create_render_pass(&info, None)?;
+
+        // Framebuffers
+
+        let mut framebuffers = Vec::with_capacity(data.swapchain_image_views.len());
+        for swapchain_view in &data.swapchain_image_views {
+            let views = attachments
+                .iter()
+                .map(|(image, _, _)| match self.transients[image.0] {
+                    Some(transient) => transient.view,
+                    None => *swapchain_view,
+                })
+                .collect::<Vec<_>>();
+
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&views)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+
+            match device.create_framebuffer(&info, None) {
+                Ok(framebuffer) => framebuffers.push(framebuffer),
+                Err(e) => {
+                    framebuffers.iter().for_each(|f| device.destroy_framebuffer(*f, None));
+                    device.destroy_render_pass(render_pass, None);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(CompiledPass {
+            render_pass,
+            framebuffers,
+            extent,
+            clear_values,
+        })
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    path::PathBuf,
+    ptr::copy_nonoverlapping as memcpy,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sllog::info;
+use vulkanalia::prelude::v1_0::*;
+
+use super::{allocator::Allocation, barrier::ImageTransition, create_buffer, output, AppData};
+use crate::MyError;
+
+/// Copy of a swapchain image recorded at the end of a frame, saved once the frame
+/// has finished rendering.
+#[derive(Clone, Debug)]
+pub(super) struct Screenshot {
+    path: PathBuf,
+    buffer: vk::Buffer,
+    memory: Allocation,
+}
+impl Screenshot {
+    pub unsafe fn create(
+        device: &Device,
+        data: &mut AppData,
+        path: PathBuf,
+    ) -> Result<Self, MyError>
+    {
+        if !Self::is_supported(data) {
+            return Err("The surface doesn't support copying from swapchain images!".into());
+        }
+
+        let extent = data.swapchain_extent;
+        let size = (extent.width * extent.height * output::get_bytes_per_pixel(data.swapchain_format)) as u64;
+
+        let (buffer, memory) = create_buffer(
+            device,
+            data,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        Ok(Self { path, buffer, memory })
+    }
+
+    /// Whether the swapchain images can be copied from.
+    pub fn is_supported(data: &AppData) -> bool {
+        data.swapchain_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC)
+    }
+
+    /// Copies the swapchain image of `image_index`, after the render graph left it
+    /// ready to present.
+    pub unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) -> Result<(), MyError>
+    {
+        let image = data.swapchain_images[image_index];
+
+        ImageTransition::new(
+            image,
+            data.swapchain_format,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        ).record(device, command_buffer)?;
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(subresource)
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: data.swapchain_extent.width,
+                height: data.swapchain_extent.height,
+                depth: 1,
+            });
+
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.buffer,
+            &[region],
+        );
+
+        ImageTransition::new(
+            image,
+            data.swapchain_format,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        ).record(device, command_buffer)?;
+
+        // Host reads happen after waiting on the frame's fence
+        let buffer_barrier = vk::BufferMemoryBarrier::builder()
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE as u64)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[buffer_barrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        Ok(())
+    }
+
+    /// Writes the copy to its path and frees the staging buffer. The frame that
+    /// recorded it must have finished.
+    pub unsafe fn save(self, device: &Device, data: &mut AppData) -> Result<(), MyError> {
+        let width = data.swapchain_extent.width;
+        let height = data.swapchain_extent.height;
+
+        let size = width * height * output::get_bytes_per_pixel(data.swapchain_format);
+
+        let pixels = self.memory.get_mapped().map(|mapped| {
+            let mut pixels = vec![0u8; size as usize];
+            memcpy(mapped, pixels.as_mut_ptr(), pixels.len());
+            pixels
+        });
+
+        // Cleanup
+
+        device.destroy_buffer(self.buffer, None);
+        data.allocator.free(device, self.memory);
+
+        let image = output::to_image(data.swapchain_format, width, height, pixels?)?;
+
+        image.save(&self.path)
+            .map_err(|e| format!("Failed to save screenshot {}: {}", self.path.display(), e))?;
+
+        info!("Screenshot saved to {}", self.path.display());
+
+        Ok(())
+    }
+}
+
+/// `screenshot_<unix time in ms>.png` in the working directory.
+pub(super) fn get_screenshot_name() -> PathBuf {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+
+    PathBuf::from(format!("screenshot_{}.png", time))
+}
@@ -0,0 +1,194 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::MyError;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+}
+impl ShaderStage {
+    const fn glslc_name(self) -> &'static str {
+        match self {
+            Self::Vertex => "vertex",
+            Self::Fragment => "fragment",
+            Self::Compute => "compute",
+        }
+    }
+    const fn glslang_name(self) -> &'static str {
+        match self {
+            Self::Vertex => "vert",
+            Self::Fragment => "frag",
+            Self::Compute => "comp",
+        }
+    }
+}
+
+enum Compiler {
+    Glslc(PathBuf),
+    GlslangValidator(PathBuf),
+}
+
+/// Whether [`compile_shader`] can find `glslc` or `glslangValidator`.
+pub(super) fn is_compiler_available() -> bool {
+    find_compiler().is_some()
+}
+
+/// Compiles the GLSL file at `path` to SPIR-V. Compile errors are reported as
+/// `file:line: message`, one per line.
+pub(super) fn compile_shader(path: &Path, stage: ShaderStage) -> Result<Vec<u8>, MyError> {
+    let compiler = find_compiler()
+        .ok_or("No GLSL compiler found, install glslc or glslangValidator or set GLSLC!")?;
+
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    let output_path = env::temp_dir().join(format!("learn_vk_{}_{}.spv", process::id(), name));
+
+    let output = match &compiler {
+        Compiler::Glslc(glslc) => Command::new(glslc)
+            .arg(format!("-fshader-stage={}", stage.glslc_name()))
+            .arg(path)
+            .arg("-o")
+            .arg(&output_path)
+            .output()?,
+        Compiler::GlslangValidator(glslang) => Command::new(glslang)
+            .args(["-V", "-S", stage.glslang_name()])
+            .arg(path)
+            .arg("-o")
+            .arg(&output_path)
+            .output()?,
+    };
+
+    if !output.status.success() {
+        // glslc reports on stderr, glslangValidator on stdout
+        let log = String::from_utf8_lossy(&output.stderr) + String::from_utf8_lossy(&output.stdout);
+
+        return Err(format!("Failed to compile {}:\n{}", path.display(), format_diagnostics(path, &log)).into());
+    }
+
+    let bytecode = fs::read(&output_path)?;
+    let _ = fs::remove_file(&output_path);
+
+    Ok(bytecode)
+}
+
+/// Polls the modification times of a set of files.
+#[derive(Clone, Debug)]
+pub(super) struct ShaderWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Instant,
+}
+impl ShaderWatcher {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            files: paths.into_iter().map(|p| {
+                let modified = modified(&p);
+                (p, modified)
+            }).collect(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Returns `true` if any file changed since the last poll. The file system is
+    /// checked at most every [`POLL_INTERVAL`].
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = false;
+        for (path, last_modified) in &mut self.files {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn find_compiler() -> Option<Compiler> {
+    if let Some(glslc) = env::var_os("GLSLC") {
+        return Some(Compiler::Glslc(PathBuf::from(glslc)));
+    }
+
+    let mut dirs = env::var_os("PATH")
+        .map(|p| env::split_paths(&p).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    if let Some(sdk) = env::var_os("VULKAN_SDK") {
+        dirs.push(Path::new(&sdk).join("bin"));
+        dirs.push(Path::new(&sdk).join("Bin"));
+    }
+
+    let find = |name: &str| dirs
+        .iter()
+        .map(|d| d.join(format!("{}{}", name, env::consts::EXE_SUFFIX)))
+        .find(|p| p.is_file());
+
+    find("glslc")
+        .map(Compiler::Glslc)
+        .or_else(|| find("glslangValidator").map(Compiler::GlslangValidator))
+}
+
+/// Rewrites the compiler output as `file:line: message` lines, dropping summaries
+/// like `1 error generated.`.
+fn format_diagnostics(path: &Path, log: &str) -> String {
+    let diagnostics = log
+        .lines()
+        .filter_map(|l| parse_diagnostic(path, l))
+        .collect::<Vec<_>>();
+
+    if diagnostics.is_empty() {
+        return log.trim().to_string();
+    }
+
+    diagnostics.join("\n")
+}
+
+/// Parses `file:line: error: message` (glslc) or `ERROR: file:line: message`
+/// (glslangValidator).
+fn parse_diagnostic(path: &Path, line: &str) -> Option<String> {
+    let (severity, line) = if let Some(rest) = line.strip_prefix("ERROR: ") {
+        (Some("error"), rest)
+    } else if let Some(rest) = line.strip_prefix("WARNING: ") {
+        (Some("warning"), rest)
+    } else {
+        (None, line)
+    };
+
+    // The file may itself contain ':' (Windows drives), so look for ":<digits>:"
+    let (file, line_number, message) = line.match_indices(':').find_map(|(i, _)| {
+        let rest = &line[i + 1..];
+        let end = rest.find(':')?;
+        let number = rest[..end].parse::<u32>().ok()?;
+
+        Some((&line[..i], number, rest[end + 1..].trim()))
+    })?;
+
+    // glslangValidator names the source string by index when it has no file name
+    let file = if file.is_empty() || file.parse::<u32>().is_ok() {
+        path.display().to_string()
+    } else {
+        file.to_string()
+    };
+
+    Some(match severity {
+        Some(severity) => format!("{}:{}: {}: {}", file, line_number, severity, message),
+        None => format!("{}:{}: {}", file, line_number, message),
+    })
+}
\ No newline at end of file
@@ -0,0 +1,164 @@
+use std::{fs, path::Path};
+
+use ddsfile::{D3DFormat, Dds, DxgiFormat};
+use image::{imageops::{self, FilterType}, RgbaImage};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::MyError;
+
+/// Texture with its mip chain already built, ready to be copied level by level.
+pub(super) struct TextureLevels {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed levels, largest first.
+    pub levels: Vec<Vec<u8>>,
+}
+
+/// Whether `path` is a precompiled texture handled by [`read_texture`] instead
+/// of the `image` crate.
+pub(super) fn is_precompiled(path: &Path) -> bool {
+    matches!(extension(path).as_deref(), Some("ktx2" | "dds"))
+}
+
+/// Reads a `.ktx2` or `.dds` file with every mip level it contains.
+pub(super) fn read_texture(path: &Path) -> Result<TextureLevels, MyError> {
+    let bytes = fs::read(path)?;
+
+    let texture = match extension(path).as_deref() {
+        Some("ktx2") => read_ktx2(&bytes),
+        Some("dds") => read_dds(&bytes),
+        _ => Err("Unknown texture container!".into()),
+    };
+
+    texture.map_err(|e| format!("Failed to load texture {}: {}", path.display(), e).into())
+}
+
+/// Builds the full mip chain of tightly packed RGBA8 `pixels` on the CPU, for
+/// formats the GPU can't blit.
+pub(super) fn generate_mip_chain(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<Vec<u8>>, MyError> {
+    let mut image = RgbaImage::from_raw(width, height, pixels.to_vec())
+        .ok_or("Texture pixels don't match its size!")?;
+
+    let mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+    let mut levels = Vec::with_capacity(mip_levels as usize);
+
+    for _ in 1..mip_levels {
+        let next = imageops::resize(
+            &image,
+            (image.width() / 2).max(1),
+            (image.height() / 2).max(1),
+            FilterType::Triangle,
+        );
+
+        levels.push(std::mem::replace(&mut image, next).into_raw());
+    }
+    levels.push(image.into_raw());
+
+    Ok(levels)
+}
+
+fn read_ktx2(bytes: &[u8]) -> Result<TextureLevels, MyError> {
+    let reader = ktx2::Reader::new(bytes).map_err(|e| e.to_string())?;
+    let header = reader.header();
+
+    if header.supercompression_scheme.is_some() {
+        return Err("supercompressed KTX2 files are not supported".into());
+    }
+    if header.layer_count > 1 || header.face_count > 1 || header.pixel_depth > 1 {
+        return Err("only single 2D images are supported".into());
+    }
+
+    // Basis Universal files have no format and must be transcoded first
+    let format = header.format.ok_or("KTX2 file has no Vulkan format")?;
+    let format = vk::Format::from_raw(format.value() as i32);
+
+    Ok(TextureLevels {
+        format,
+        width: header.pixel_width,
+        height: header.pixel_height.max(1),
+        levels: reader.levels().map(|l| l.data.to_vec()).collect(),
+    })
+}
+
+fn read_dds(bytes: &[u8]) -> Result<TextureLevels, MyError> {
+    let dds = Dds::read(bytes)?;
+
+    if dds.get_num_array_layers() > 1 || dds.get_depth() > 1 {
+        return Err("only single 2D images are supported".into());
+    }
+
+    let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+        (Some(format), _) => dxgi_format(format),
+        (None, Some(format)) => d3d_format(format),
+        _ => None,
+    }
+    .ok_or("unsupported DDS format")?;
+
+    let data_format = dds.get_format().ok_or("unsupported DDS format")?;
+    let data = dds.get_data(0)?;
+
+    let mut levels = Vec::new();
+    let mut offset = 0;
+    let (mut width, mut height) = (dds.get_width(), dds.get_height());
+
+    for _ in 0..dds.get_num_mipmap_levels().max(1) {
+        let pitch = data_format.get_pitch(width).ok_or("unsupported DDS format")? as usize;
+        let rows = height.div_ceil(data_format.get_pitch_height()) as usize;
+        let size = pitch * rows;
+
+        let level = data.get(offset..offset + size).ok_or("DDS mip chain is truncated")?;
+        levels.push(level.to_vec());
+
+        offset += size;
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+    }
+
+    Ok(TextureLevels {
+        format,
+        width: dds.get_width(),
+        height: dds.get_height(),
+        levels,
+    })
+}
+
+fn dxgi_format(format: DxgiFormat) -> Option<vk::Format> {
+    Some(match format {
+        DxgiFormat::R8G8B8A8_UNorm => vk::Format::R8G8B8A8_UNORM,
+        DxgiFormat::R8G8B8A8_UNorm_sRGB => vk::Format::R8G8B8A8_SRGB,
+        DxgiFormat::B8G8R8A8_UNorm => vk::Format::B8G8R8A8_UNORM,
+        DxgiFormat::B8G8R8A8_UNorm_sRGB => vk::Format::B8G8R8A8_SRGB,
+        DxgiFormat::BC1_UNorm => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        DxgiFormat::BC1_UNorm_sRGB => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        DxgiFormat::BC2_UNorm => vk::Format::BC2_UNORM_BLOCK,
+        DxgiFormat::BC2_UNorm_sRGB => vk::Format::BC2_SRGB_BLOCK,
+        DxgiFormat::BC3_UNorm => vk::Format::BC3_UNORM_BLOCK,
+        DxgiFormat::BC3_UNorm_sRGB => vk::Format::BC3_SRGB_BLOCK,
+        DxgiFormat::BC4_UNorm => vk::Format::BC4_UNORM_BLOCK,
+        DxgiFormat::BC4_SNorm => vk::Format::BC4_SNORM_BLOCK,
+        DxgiFormat::BC5_UNorm => vk::Format::BC5_UNORM_BLOCK,
+        DxgiFormat::BC5_SNorm => vk::Format::BC5_SNORM_BLOCK,
+        DxgiFormat::BC6H_UF16 => vk::Format::BC6H_UFLOAT_BLOCK,
+        DxgiFormat::BC6H_SF16 => vk::Format::BC6H_SFLOAT_BLOCK,
+        DxgiFormat::BC7_UNorm => vk::Format::BC7_UNORM_BLOCK,
+        DxgiFormat::BC7_UNorm_sRGB => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    })
+}
+
+/// Legacy headers don't say whether the data is sRGB, color textures are assumed.
+fn d3d_format(format: D3DFormat) -> Option<vk::Format> {
+    Some(match format {
+        D3DFormat::A8B8G8R8 => vk::Format::R8G8B8A8_SRGB,
+        D3DFormat::A8R8G8B8 => vk::Format::B8G8R8A8_SRGB,
+        D3DFormat::DXT1 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        D3DFormat::DXT3 => vk::Format::BC2_SRGB_BLOCK,
+        D3DFormat::DXT5 => vk::Format::BC3_SRGB_BLOCK,
+        _ => return None,
+    })
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().map(|e| e.to_string_lossy().to_lowercase())
+}
@@ -0,0 +1,535 @@
+use std::{mem::size_of_val, ptr::copy_nonoverlapping as memcpy};
+
+use vulkanalia::prelude::v1_0::*;
+
+use super::{
+    allocator::Allocation, barrier::ImageTransition, create_buffer, generate_mipmaps, AppData,
+    QueueFamilyIndices,
+};
+use crate::MyError;
+
+/// Multiple of every texel block size up to 32 bytes and of 4, for formats
+/// without a known block size.
+const FALLBACK_LEVEL_ALIGNMENT: usize = 96;
+
+/// Resource that can't be used until its upload finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum UploadTarget {
+    /// Identified by the vertex buffer.
+    Mesh(vk::Buffer),
+    Texture(vk::Image),
+}
+
+/// Ownership of a resource released by the transfer queue, to be acquired by the
+/// graphics queue once the copy finished.
+#[derive(Clone, Copy, Debug)]
+enum Acquire {
+    Buffer {
+        buffer: vk::Buffer,
+        stage: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+    },
+    /// Acquired in `TRANSFER_DST_OPTIMAL`, then mipmapped on the graphics queue
+    /// unless every level was uploaded.
+    Image {
+        image: vk::Image,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        generate_mips: bool,
+    },
+}
+
+/// How the graphics queue uses a buffer uploaded by [`upload_buffer`].
+#[derive(Clone, Copy, Debug)]
+pub(super) struct BufferUsage {
+    pub usage: vk::BufferUsageFlags,
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+/// Tightly packed mip levels of `image`, largest first.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct ImageLevels<'a> {
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+    pub levels: &'a [&'a [u8]],
+}
+
+/// Commands being recorded for the transfer queue.
+pub(super) struct Upload {
+    command_buffer: vk::CommandBuffer,
+    staging: Vec<(vk::Buffer, Allocation)>,
+    acquires: Vec<Acquire>,
+    /// `(src, dst)` families of the ownership transfer, ignored when they match.
+    families: (u32, u32),
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct PendingUpload {
+    target: UploadTarget,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    staging: Vec<(vk::Buffer, Allocation)>,
+    acquires: Vec<Acquire>,
+    families: (u32, u32),
+}
+
+pub(super) unsafe fn create_transfer_command_pool(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData
+) -> Result<(), MyError>
+{
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::empty())
+        .queue_family_index(indices.transfer);
+
+    data.transfer_command_pool = device.create_command_pool(&info, None)?;
+
+    Ok(())
+}
+
+pub(super) unsafe fn begin_upload(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData
+) -> Result<Upload, MyError>
+{
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let families = if indices.transfer == indices.graphics {
+        (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+    } else {
+        (indices.transfer, indices.graphics)
+    };
+
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(data.transfer_command_pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    Ok(Upload {
+        command_buffer,
+        staging: Vec::new(),
+        acquires: Vec::new(),
+        families,
+    })
+}
+
+/// Records a copy of `items` into a new device local buffer used as `usage`
+/// describes.
+pub(super) unsafe fn upload_buffer<T: Copy>(
+    device: &Device,
+    data: &mut AppData,
+    upload: &mut Upload,
+    items: &[T],
+    usage: BufferUsage,
+) -> Result<(vk::Buffer, Allocation), MyError>
+{
+    // Vulkan doesn't allow empty buffers
+    if items.is_empty() {
+        return Err("Can't upload an empty buffer!".into());
+    }
+
+    let size = size_of_val(items) as u64;
+    let staging = create_staging_buffer(device, data, upload, items.as_ptr().cast(), size)?;
+
+    // Create (buffer)
+
+    let (buffer, buffer_memory) = create_buffer(
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST | usage.usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    // Copy (buffer)
+
+    let regions = vk::BufferCopy::builder().size(size);
+
+    device.cmd_copy_buffer(upload.command_buffer, staging, buffer, &[regions]);
+
+    // Release
+
+    let barrier = buffer_barrier(buffer, upload.families)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::empty());
+
+    device.cmd_pipeline_barrier(
+        upload.command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    upload.acquires.push(Acquire::Buffer { buffer, stage: usage.stage, access: usage.access });
+
+    Ok((buffer, buffer_memory))
+}
+
+/// Records a copy of `levels` into their image. Either every mip level is provided
+/// or only level 0, in which case the others are generated on the graphics queue.
+/// Every level ends in `SHADER_READ_ONLY_OPTIMAL`.
+pub(super) unsafe fn upload_image(
+    device: &Device,
+    data: &mut AppData,
+    upload: &mut Upload,
+    levels: ImageLevels,
+) -> Result<(), MyError>
+{
+    let ImageLevels { image, format, width, height, mip_levels, levels } = levels;
+
+    // Every level shares one staging buffer, offsets must be multiples of the texel
+    // block size and of 4
+    let alignment = get_texel_block_size(format)
+        .map_or(FALLBACK_LEVEL_ALIGNMENT, |size| lcm(size, 4));
+
+    let mut pixels = Vec::new();
+    let mut offsets = Vec::with_capacity(levels.len());
+    for level in levels {
+        pixels.resize(pixels.len().next_multiple_of(alignment), 0);
+        offsets.push(pixels.len() as u64);
+        pixels.extend_from_slice(level);
+    }
+
+    let staging = create_staging_buffer(device, data, upload, pixels.as_ptr(), pixels.len() as u64)?;
+
+    // Transition
+
+    ImageTransition::new(image, format, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .with_mip_levels(0, mip_levels)
+        .record(device, upload.command_buffer)?;
+
+    // Copy (image)
+
+    let regions = offsets
+        .iter()
+        .enumerate()
+        .map(|(i, offset)| {
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(i as u32)
+                .base_array_layer(0)
+                .layer_count(1);
+
+            vk::BufferImageCopy::builder()
+                .buffer_offset(*offset)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(subresource)
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width: (width >> i).max(1),
+                    height: (height >> i).max(1),
+                    depth: 1,
+                })
+        })
+        .collect::<Vec<_>>();
+
+    device.cmd_copy_buffer_to_image(
+        upload.command_buffer,
+        staging,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &regions,
+    );
+
+    // Release
+
+    get_ownership_transfer(image, format, mip_levels, upload.families)
+        .record(device, upload.command_buffer)?;
+
+    upload.acquires.push(Acquire::Image {
+        image,
+        format,
+        width,
+        height,
+        mip_levels,
+        generate_mips: (levels.len() as u32) < mip_levels,
+    });
+
+    Ok(())
+}
+
+/// Submits the upload to the transfer queue without waiting for it. `target`
+/// stays pending until [`poll_uploads`] sees the copy finished.
+pub(super) unsafe fn submit_upload(
+    device: &Device,
+    data: &mut AppData,
+    upload: Upload,
+    target: UploadTarget,
+) -> Result<(), MyError>
+{
+    device.end_command_buffer(upload.command_buffer)?;
+
+    let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None)?;
+
+    let command_buffers = &[upload.command_buffer];
+    let info = vk::SubmitInfo::builder()
+        .command_buffers(command_buffers);
+
+    device.queue_submit(data.transfer_queue, &[info], fence)?;
+
+    data.pending_uploads.push(PendingUpload {
+        target,
+        command_buffer: upload.command_buffer,
+        fence,
+        staging: upload.staging,
+        acquires: upload.acquires,
+        families: upload.families,
+    });
+
+    Ok(())
+}
+
+pub(super) fn is_pending(data: &AppData, target: UploadTarget) -> bool {
+    data.pending_uploads.iter().any(|u| u.target == target)
+}
+
+/// Hands finished uploads over to the graphics queue without blocking. Returns
+/// `true` if any resource became usable.
+pub(super) unsafe fn poll_uploads(device: &Device, data: &mut AppData) -> Result<bool, MyError> {
+    // Acquire submissions that finished executing
+    let mut retired = Vec::new();
+    for (command_buffer, fence) in &data.acquire_submissions {
+        if device.get_fence_status(*fence)? == vk::SuccessCode::SUCCESS {
+            retired.push((*command_buffer, *fence));
+        }
+    }
+    for (command_buffer, fence) in &retired {
+        device.free_command_buffers(data.command_pool, &[*command_buffer]);
+        device.destroy_fence(*fence, None);
+    }
+    data.acquire_submissions.retain(|s| !retired.contains(s));
+
+    // Uploads whose copy finished
+    let mut finished = Vec::new();
+    let mut i = 0;
+    while i < data.pending_uploads.len() {
+        if device.get_fence_status(data.pending_uploads[i].fence)? == vk::SuccessCode::SUCCESS {
+            finished.push(data.pending_uploads.remove(i));
+        } else {
+            i += 1;
+        }
+    }
+
+    if finished.is_empty() {
+        return Ok(false);
+    }
+
+    // Acquire
+
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(data.command_pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    for upload in &finished {
+        for acquire in &upload.acquires {
+            record_acquire(device, command_buffer, acquire, upload.families)?;
+        }
+    }
+
+    device.end_command_buffer(command_buffer)?;
+
+    // Later frames are submitted to the same queue, so they run after the acquire
+    let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None)?;
+
+    let command_buffers = &[command_buffer];
+    let info = vk::SubmitInfo::builder()
+        .command_buffers(command_buffers);
+
+    device.queue_submit(data.graphics_queue, &[info], fence)?;
+
+    data.acquire_submissions.push((command_buffer, fence));
+
+    // Cleanup
+
+    for upload in &finished {
+        destroy_upload(device, data, upload);
+    }
+
+    Ok(true)
+}
+
+/// Blocks until every pending upload finished and was handed to the graphics queue.
+/// Returns `true` if any resource became usable.
+pub(super) unsafe fn wait_for_uploads(device: &Device, data: &mut AppData) -> Result<bool, MyError> {
+    let fences = data.pending_uploads
+        .iter()
+        .map(|u| u.fence)
+        .collect::<Vec<_>>();
+
+    if !fences.is_empty() {
+        device.wait_for_fences(&fences, true, u64::MAX)?;
+    }
+
+    poll_uploads(device, data)
+}
+
+/// Frees every upload resource. The device must be idle.
+pub(super) unsafe fn destroy_uploads(device: &Device, data: &mut AppData) {
+    for upload in std::mem::take(&mut data.pending_uploads) {
+        destroy_upload(device, data, &upload);
+    }
+
+    for (command_buffer, fence) in std::mem::take(&mut data.acquire_submissions) {
+        device.free_command_buffers(data.command_pool, &[command_buffer]);
+        device.destroy_fence(fence, None);
+    }
+}
+
+unsafe fn destroy_upload(device: &Device, data: &mut AppData, upload: &PendingUpload) {
+    device.free_command_buffers(data.transfer_command_pool, &[upload.command_buffer]);
+    device.destroy_fence(upload.fence, None);
+
+    for (buffer, memory) in &upload.staging {
+        device.destroy_buffer(*buffer, None);
+        data.allocator.free(device, *memory);
+    }
+}
+
+unsafe fn record_acquire(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    acquire: &Acquire,
+    families: (u32, u32),
+) -> Result<(), MyError>
+{
+    match *acquire {
+        Acquire::Buffer { buffer, stage, access } => {
+            let barrier = buffer_barrier(buffer, families)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(access);
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                stage,
+                vk::DependencyFlags::empty(),
+                &[] as &[vk::MemoryBarrier],
+                &[barrier],
+                &[] as &[vk::ImageMemoryBarrier],
+            );
+        },
+        Acquire::Image { image, format, width, height, mip_levels, generate_mips } => {
+            get_ownership_transfer(image, format, mip_levels, families).record(device, command_buffer)?;
+
+            if generate_mips {
+                // Blits need a graphics queue
+                return generate_mipmaps(device, command_buffer, image, format, width, height, mip_levels);
+            }
+
+            ImageTransition::new(
+                image,
+                format,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+            .with_mip_levels(0, mip_levels)
+            .record(device, command_buffer)?;
+        },
+    }
+
+    Ok(())
+}
+
+unsafe fn create_staging_buffer(
+    device: &Device,
+    data: &mut AppData,
+    upload: &mut Upload,
+    source: *const u8,
+    size: u64,
+) -> Result<vk::Buffer, MyError>
+{
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    memcpy(source, staging_buffer_memory.get_mapped()?, size as usize);
+
+    upload.staging.push((staging_buffer, staging_buffer_memory));
+
+    Ok(staging_buffer)
+}
+
+fn buffer_barrier(buffer: vk::Buffer, families: (u32, u32)) -> vk::BufferMemoryBarrierBuilder<'static> {
+    vk::BufferMemoryBarrier::builder()
+        .src_queue_family_index(families.0)
+        .dst_queue_family_index(families.1)
+        .buffer(buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE as u64)
+}
+
+/// Release or acquire of every mip level, which stay in `TRANSFER_DST_OPTIMAL`.
+fn get_ownership_transfer(image: vk::Image, format: vk::Format, mip_levels: u32, families: (u32, u32)) -> ImageTransition {
+    ImageTransition::new(
+        image,
+        format,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    )
+    .with_mip_levels(0, mip_levels)
+    .with_queue_families(families.0, families.1)
+}
+/// Bytes per texel, or per block of compressed formats. `None` for depth/stencil
+/// and extension formats.
+fn get_texel_block_size(format: vk::Format) -> Option<usize> {
+    Some(match format.as_raw() {
+        1 | 9..=15 => 1,
+        2..=8 | 16..=22 | 70..=76 => 2,
+        23..=36 => 3,
+        37..=69 | 77..=83 | 98..=100 | 122 | 123 => 4,
+        84..=90 => 6,
+        91..=97 | 101..=103 | 110..=112 => 8,
+        104..=106 => 12,
+        107..=109 | 113..=115 => 16,
+        116..=118 => 24,
+        119..=121 => 32,
+        // BC1, BC4, ETC2 RGB(A1) and EAC R11
+        131..=134 | 139 | 140 | 147..=150 | 153 | 154 => 8,
+        // BC2, BC3, BC5-7, ETC2 RGBA8, EAC RG11 and ASTC
+        135..=138 | 141..=146 | 151 | 152 | 155..=184 => 16,
+        _ => return None,
+    })
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    let gcd = |mut a: usize, mut b: usize| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+
+    a / gcd(a, b) * b
+}
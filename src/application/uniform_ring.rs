@@ -0,0 +1,116 @@
+use std::{
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ptr::copy_nonoverlapping as memcpy,
+};
+
+use vulkanalia::prelude::v1_0::*;
+
+use super::{
+    allocator::{Allocation, Allocator},
+    create_buffer, AppData,
+};
+use crate::MyError;
+
+/// Bytes of the slices each frame may reserve.
+const FRAME_SIZE: u64 = 64 * 1024;
+
+/// Typed range of a [`UniformRing`] frame.
+#[derive(Debug)]
+pub(super) struct RingSlice<T> {
+    pub buffer: vk::Buffer,
+    pub offset: u64,
+    _marker: PhantomData<T>,
+}
+impl<T> RingSlice<T> {
+    pub const fn get_range(&self) -> u64 {
+        size_of::<T>() as u64
+    }
+}
+impl<T> Clone for RingSlice<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for RingSlice<T> {}
+
+/// Persistently mapped uniform buffer split into one region per frame, holding
+/// slices reserved once and bound by static descriptors. Every slice honors
+/// `minUniformBufferOffsetAlignment`.
+#[derive(Clone, Debug, Default)]
+pub(super) struct UniformRing {
+    buffer: vk::Buffer,
+    memory: Allocation,
+    alignment: u64,
+    /// End of the reserved slices of each frame.
+    reserved: Vec<u64>,
+}
+impl UniformRing {
+    pub unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &mut AppData,
+        frame_count: usize,
+    ) -> Result<Self, MyError>
+    {
+        let alignment = instance
+            .get_physical_device_properties(data.physical_device)
+            .limits
+            .min_uniform_buffer_offset_alignment
+            .max(1);
+
+        let (buffer, memory) = create_buffer(
+            device,
+            data,
+            FRAME_SIZE * frame_count as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        Ok(Self {
+            buffer,
+            memory,
+            alignment,
+            reserved: (0..frame_count as u64).map(|f| f * FRAME_SIZE).collect(),
+        })
+    }
+
+    /// Reserves a slice of `frame` that keeps its offset for the ring's lifetime.
+    pub fn reserve<T: Copy>(&mut self, frame: usize) -> Result<RingSlice<T>, MyError> {
+        let offset = self.allocate::<T>(frame, self.reserved[frame])?;
+        self.reserved[frame] = offset + size_of::<T>() as u64;
+
+        Ok(self.get_slice(offset))
+    }
+
+    pub unsafe fn write<T: Copy>(&self, slice: RingSlice<T>, value: &T) -> Result<(), MyError> {
+        let memory = self.memory.get_mapped()?.add(slice.offset as usize);
+
+        memcpy(value, memory.cast(), 1);
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&self, device: &Device, allocator: &mut Allocator) {
+        device.destroy_buffer(self.buffer, None);
+        allocator.free(device, self.memory);
+    }
+
+    fn allocate<T>(&self, frame: usize, head: u64) -> Result<u64, MyError> {
+        let offset = head.next_multiple_of(self.alignment.max(align_of::<T>() as u64));
+
+        if offset + size_of::<T>() as u64 > (frame as u64 + 1) * FRAME_SIZE {
+            return Err(format!("Uniform ring frame exceeded {} bytes!", FRAME_SIZE).into());
+        }
+
+        Ok(offset)
+    }
+
+    fn get_slice<T>(&self, offset: u64) -> RingSlice<T> {
+        RingSlice {
+            buffer: self.buffer,
+            offset,
+            _marker: PhantomData,
+        }
+    }
+}
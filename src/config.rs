@@ -0,0 +1,205 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use winit::event::VirtualKeyCode;
+
+use crate::MyError;
+
+/// Overrides [`AppConfig::device`] with a device index or a part of its name.
+pub const DEVICE_ENV: &str = "LEARN_VK_DEVICE";
+
+/// Physical device to render on instead of the highest scoring one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Position in `vkEnumeratePhysicalDevices`.
+    Index(usize),
+    /// Case insensitive part of the device name.
+    Name(String),
+}
+
+/// How finished frames are presented. Modes the surface doesn't support fall back
+/// to the closest one, `Vsync` is always available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentMode {
+    /// Waits for vertical blank, capping the frame rate at the refresh rate.
+    Vsync,
+    /// Like `Vsync`, but a late frame is shown right away and may tear.
+    RelaxedVsync,
+    /// Uncapped without tearing, the newest frame replaces a waiting one. Falls
+    /// back to `Vsync` rather than tearing.
+    Mailbox,
+    /// Uncapped, frames are shown right away and may tear.
+    #[default]
+    Immediate,
+}
+
+/// Format and color space of the swapchain, or of the offscreen image of headless
+/// apps. HDR and wide-gamut outputs need `VK_EXT_swapchain_colorspace` and a surface
+/// supporting them, otherwise `Sdr` is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// 8 bit sRGB.
+    #[default]
+    Sdr,
+    /// 10 bit sRGB, screenshots are saved as 16 bit PNGs.
+    Sdr10,
+    /// 16 bit float linear extended sRGB (scRGB), values above 1.0 are brighter
+    /// than SDR white.
+    ExtendedSrgb,
+    /// 10 bit Rec. 2020 with the PQ transfer function.
+    Hdr10,
+}
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub asset_root: PathBuf,
+    pub model_path: PathBuf,
+    pub texture_path: PathBuf,
+    /// Directory of the GLSL sources, relative to `asset_root`.
+    pub shader_dir: PathBuf,
+    /// Rebuilds the pipeline when a shader source changes.
+    pub shader_hot_reload: bool,
+    /// Clamped to the device support, `1` disables MSAA and `None` uses the maximum.
+    pub msaa_samples: Option<u32>,
+    /// Saves the next frame to the working directory when pressed.
+    pub screenshot_key: Option<VirtualKeyCode>,
+    /// `None` picks the highest scoring device, discrete GPUs first.
+    pub device: Option<DeviceSelector>,
+    pub present_mode: PresentMode,
+    pub output_format: OutputFormat,
+    /// Pipeline cache file, loaded at startup and saved when the app is destroyed.
+    /// `None` keeps the cache in memory only.
+    pub pipeline_cache_path: Option<PathBuf>,
+}
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            asset_root: PathBuf::from("assets"),
+            model_path: PathBuf::from("objects/viking_room.obj"),
+            texture_path: PathBuf::from("textures/viking_room.png"),
+            shader_dir: PathBuf::from("shaders"),
+            shader_hot_reload: true,
+            msaa_samples: None,
+            screenshot_key: Some(VirtualKeyCode::F12),
+            device: None,
+            present_mode: PresentMode::default(),
+            output_format: OutputFormat::default(),
+            pipeline_cache_path: Some(PathBuf::from("pipeline_cache.bin")),
+        }
+    }
+}
+impl AppConfig {
+    // Public
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_asset_root(mut self, asset_root: impl Into<PathBuf>) -> Self {
+        self.asset_root = asset_root.into();
+        self
+    }
+    pub fn with_model_path(mut self, model_path: impl Into<PathBuf>) -> Self {
+        self.model_path = model_path.into();
+        self
+    }
+    pub fn with_texture_path(mut self, texture_path: impl Into<PathBuf>) -> Self {
+        self.texture_path = texture_path.into();
+        self
+    }
+    pub fn with_shader_dir(mut self, shader_dir: impl Into<PathBuf>) -> Self {
+        self.shader_dir = shader_dir.into();
+        self
+    }
+    pub fn with_shader_hot_reload(mut self, shader_hot_reload: bool) -> Self {
+        self.shader_hot_reload = shader_hot_reload;
+        self
+    }
+    pub fn with_msaa_samples(mut self, msaa_samples: u32) -> Self {
+        self.msaa_samples = Some(msaa_samples);
+        self
+    }
+    pub fn with_screenshot_key(mut self, screenshot_key: Option<VirtualKeyCode>) -> Self {
+        self.screenshot_key = screenshot_key;
+        self
+    }
+    pub fn with_device(mut self, device: DeviceSelector) -> Self {
+        self.device = Some(device);
+        self
+    }
+    pub fn with_present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+    pub fn with_pipeline_cache_path(mut self, pipeline_cache_path: Option<PathBuf>) -> Self {
+        self.pipeline_cache_path = pipeline_cache_path;
+        self
+    }
+
+    pub fn get_model_path(&self) -> Result<PathBuf, MyError> {
+        self.resolve_asset(&self.model_path)
+    }
+    pub fn get_texture_path(&self) -> Result<PathBuf, MyError> {
+        self.resolve_asset(&self.texture_path)
+    }
+    pub fn get_shader_path(&self, name: impl AsRef<Path>) -> Result<PathBuf, MyError> {
+        self.resolve_asset(&self.shader_dir.join(name))
+    }
+    /// [`DEVICE_ENV`] when set, [`AppConfig::device`] otherwise.
+    pub fn get_device(&self) -> Option<DeviceSelector> {
+        match env::var(DEVICE_ENV) {
+            Ok(value) if !value.trim().is_empty() => Some(match value.trim().parse() {
+                Ok(index) => DeviceSelector::Index(index),
+                Err(_) => DeviceSelector::Name(value.trim().to_string()),
+            }),
+            _ => self.device.clone(),
+        }
+    }
+
+    /// Resolves `path` against the asset root, first relative to the working
+    /// directory and then relative to `CARGO_MANIFEST_DIR`.
+    pub fn resolve_asset(&self, path: &Path) -> Result<PathBuf, MyError> {
+        let candidates = self.candidates(path);
+
+        if let Some(found) = candidates.iter().find(|c| c.is_file()) {
+            return Ok(found.clone());
+        }
+
+        let searched = candidates
+            .iter()
+            .map(|c| format!("\n  {}", c.display()))
+            .collect::<String>();
+
+        Err(format!("Asset not found: {}, searched:{}", path.display(), searched).into())
+    }
+
+    // Private
+    fn candidates(&self, path: &Path) -> Vec<PathBuf> {
+        if path.is_absolute() {
+            return vec![path.to_path_buf()];
+        }
+
+        let relative = self.asset_root.join(path);
+        if relative.is_absolute() {
+            return vec![relative];
+        }
+
+        let mut result = Vec::new();
+
+        if let Ok(current_dir) = env::current_dir() {
+            result.push(current_dir.join(&relative));
+        }
+        if let Some(manifest_dir) = env::var_os("CARGO_MANIFEST_DIR") {
+            result.push(PathBuf::from(manifest_dir).join(&relative));
+        }
+        result.push(Path::new(env!("CARGO_MANIFEST_DIR")).join(&relative));
+
+        result.dedup();
+        result
+    }
+}
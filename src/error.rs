@@ -0,0 +1,94 @@
+use std::{error::Error, fmt, io};
+
+use vulkanalia::{bytecode::BytecodeError, vk};
+
+#[derive(Debug)]
+pub enum MyError {
+    /// The swapchain no longer matches the surface and must be recreated.
+    OutOfDate,
+    /// The window system destroyed the surface, it must be recreated from the window.
+    SurfaceLost,
+    /// The device was lost, every GPU object must be recreated.
+    DeviceLost,
+    /// Any other Vulkan error.
+    Vulkan(vk::ErrorCode),
+    /// Missing assets, shader compile errors and everything else not caused by the GPU.
+    Other(Box<dyn Error>),
+}
+impl MyError {
+    /// Whether [`App::recover`](crate::application::App::recover) can get the app
+    /// rendering again. Any other error is fatal.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Self::OutOfDate | Self::SurfaceLost | Self::DeviceLost)
+    }
+}
+impl fmt::Display for MyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OutOfDate => write!(f, "Swapchain is out of date"),
+            Self::SurfaceLost => write!(f, "Surface lost"),
+            Self::DeviceLost => write!(f, "Device lost"),
+            Self::Vulkan(e) => write!(f, "Vulkan error: {}", e),
+            Self::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl Error for MyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Vulkan(e) => Some(e),
+            Self::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<vk::ErrorCode> for MyError {
+    fn from(value: vk::ErrorCode) -> Self {
+        match value {
+            vk::ErrorCode::OUT_OF_DATE_KHR => Self::OutOfDate,
+            vk::ErrorCode::SURFACE_LOST_KHR => Self::SurfaceLost,
+            vk::ErrorCode::DEVICE_LOST => Self::DeviceLost,
+            e => Self::Vulkan(e),
+        }
+    }
+}
+impl From<&str> for MyError {
+    fn from(value: &str) -> Self {
+        Self::Other(value.into())
+    }
+}
+impl From<String> for MyError {
+    fn from(value: String) -> Self {
+        Self::Other(value.into())
+    }
+}
+impl From<Box<dyn Error + Send + Sync>> for MyError {
+    fn from(value: Box<dyn Error + Send + Sync>) -> Self {
+        Self::Other(value)
+    }
+}
+
+/// Errors of the libraries used to load assets and create the window, reported as
+/// [`MyError::Other`].
+macro_rules! impl_from_other {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for MyError {
+                fn from(value: $error) -> Self {
+                    Self::Other(Box::new(value))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_other!(
+    io::Error,
+    image::ImageError,
+    tobj::LoadError,
+    gltf::Error,
+    ddsfile::Error,
+    BytecodeError,
+    winit::error::OsError,
+);
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use winit::event::{
     ElementState, VirtualKeyCode,
@@ -15,6 +15,8 @@ pub struct MouseBtn {
 #[derive(Debug, Clone)]
 pub struct Input {
     key_states: HashMap<VirtualKeyCode, ElementState>,    
+    /// Keys pressed since they were last taken with `take_key_press`.
+    key_presses: HashSet<VirtualKeyCode>,
     mouse_states: Vec<MouseBtn>,
     mouse_position: glm::Vec2,
 }
@@ -22,6 +24,7 @@ impl Input {
     pub fn new() -> Self {
         Self { 
             key_states: HashMap::new(),
+            key_presses: HashSet::new(),
             mouse_states: Vec::new(),
             mouse_position: glm::vec2(0.0, 0.0),
         }
@@ -57,6 +60,11 @@ impl Input {
     }
 
     pub fn set_key_state(&mut self, key_code: VirtualKeyCode, state: ElementState) {
+        // Key repeat sends Pressed again without a Released in between
+        if state == ElementState::Pressed && !self.is_key_pressed(key_code) {
+            self.key_presses.insert(key_code);
+        }
+
         self.key_states.insert(key_code, state);
     }
     pub fn is_key_pressed(&self, key: VirtualKeyCode) -> bool {
@@ -65,4 +73,9 @@ impl Input {
         
         *state == ElementState::Pressed
     }
+    /// Whether `key` went down since the last call, for actions that happen once
+    /// per press.
+    pub fn take_key_press(&mut self, key: VirtualKeyCode) -> bool {
+        self.key_presses.remove(&key)
+    }
 }
\ No newline at end of file
@@ -1,6 +1,10 @@
-pub type MyError = Box<dyn std::error::Error>;
+pub mod error;
 pub mod utils;
 pub mod window;
 pub mod application;
+pub mod config;
 pub mod camera;
-pub mod input;
\ No newline at end of file
+pub mod input;
+pub mod scene;
+
+pub use error::MyError;
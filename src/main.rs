@@ -6,10 +6,10 @@
 
 use std::env;
 
-use learn_vk::{application::App, window::get_event_loop};
+use learn_vk::{application::App, config::AppConfig, window::get_event_loop};
 use learn_vk::MyError;
 
-use sllog::info;
+use sllog::{error, info, warn};
 use winit::event::MouseScrollDelta;
 use winit::{
     dpi::LogicalSize,
@@ -24,6 +24,13 @@ use winit::{
 fn main() -> Result<(), MyError> {
     env::set_var("LOG", "4");
 
+    // Headless: `learn_vk --headless [output.png]`
+    let args = env::args().collect::<Vec<_>>();
+    if let Some(i) = args.iter().position(|a| a == "--headless") {
+        let output = args.get(i + 1).map(String::as_str).unwrap_or("output.png");
+        return render_headless(output, &AppConfig::default());
+    }
+
     // Window
     let event_loop = get_event_loop();
     let window = WindowBuilder::new()
@@ -32,14 +39,29 @@ fn main() -> Result<(), MyError> {
         .build(&event_loop)?;
 
     // App
-    let mut app = unsafe { App::create(&window)? };
+    let config = AppConfig::default();
+    let mut app = unsafe { App::create(&window, &config)? };
     let mut destroying = false;
     let mut minimized = false;
     
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::MainEventsCleared if !destroying && !minimized => {
-                unsafe { app.render(&window).unwrap() }
+                let result = unsafe { app.render(&window) }.or_else(|e| {
+                    if !e.is_recoverable() {
+                        return Err(e);
+                    }
+
+                    warn!("{}, recovering", e);
+                    unsafe { app.recover(&window, &e) }
+                });
+
+                if let Err(e) = result {
+                    error!("{}", e);
+                    destroying = true;
+                    *control_flow = ControlFlow::Exit;
+                    unsafe { app.destroy(); }
+                }
             }
             Event::WindowEvent {event, .. } => {
                 match event {
@@ -81,3 +103,13 @@ fn main() -> Result<(), MyError> {
     });
 }
 
+fn render_headless(output: &str, config: &AppConfig) -> Result<(), MyError> {
+    let mut app = unsafe { App::create_headless(1024, 768, config)? };
+    let image = unsafe { app.render_headless()? };
+    unsafe { app.destroy(); }
+
+    image.save(output)?;
+    info!("Headless frame saved to {}", output);
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use nalgebra_glm as glm;
+
+pub type MeshId = usize;
+pub type TextureId = usize;
+
+/// Mesh and texture loaded by [`App::create`](crate::application::App::create).
+pub const DEFAULT_MESH: MeshId = 0;
+pub const DEFAULT_TEXTURE: TextureId = 0;
+
+/// Point lights past this count are ignored by the shader.
+pub const MAX_POINT_LIGHTS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MeshInstance {
+    pub mesh: MeshId,
+    pub transform: glm::Mat4,
+    /// Overrides the textures of the mesh materials when set.
+    pub texture: Option<TextureId>,
+}
+impl MeshInstance {
+    pub fn new(mesh: MeshId, transform: glm::Mat4) -> Self {
+        Self {
+            mesh,
+            transform,
+            texture: None,
+        }
+    }
+
+    pub fn with_texture(mut self, texture: TextureId) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight {
+    /// Direction the light travels in, world space.
+    pub direction: glm::Vec3,
+    pub color: glm::Vec3,
+    pub intensity: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    pub position: glm::Vec3,
+    pub color: glm::Vec3,
+    pub intensity: f32,
+    /// Distance at which the light fades out completely.
+    pub range: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scene {
+    instances: Vec<MeshInstance>,
+    pub ambient_light: glm::Vec3,
+    pub directional_light: Option<DirectionalLight>,
+    pub point_lights: Vec<PointLight>,
+}
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            instances: Vec::new(),
+            ambient_light: glm::vec3(0.1, 0.1, 0.1),
+            directional_light: Some(DirectionalLight {
+                direction: glm::vec3(-0.5, -1.0, -0.5),
+                color: glm::vec3(1.0, 1.0, 1.0),
+                intensity: 1.0,
+            }),
+            point_lights: Vec::new(),
+        }
+    }
+}
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the index of the new instance.
+    pub fn add_instance(&mut self, instance: MeshInstance) -> usize {
+        self.instances.push(instance);
+        self.instances.len() - 1
+    }
+    pub fn remove_instance(&mut self, index: usize) -> MeshInstance {
+        self.instances.remove(index)
+    }
+    pub fn clear(&mut self) {
+        self.instances.clear();
+    }
+
+    pub fn get_instances(&self) -> &[MeshInstance] {
+        &self.instances
+    }
+    pub fn get_instance_mut(&mut self, index: usize) -> Option<&mut MeshInstance> {
+        self.instances.get_mut(index)
+    }
+}
+
+/// Node of an imported model hierarchy. `instances` index into the [`Scene`].
+#[derive(Debug, Clone)]
+pub struct SceneNode {
+    pub name: Option<String>,
+    pub local_transform: glm::Mat4,
+    pub world_transform: glm::Mat4,
+    pub children: Vec<usize>,
+    pub instances: Vec<usize>,
+}
+
+/// GPU resources and hierarchy created by [`App::load_gltf`](crate::application::App::load_gltf).
+#[derive(Debug, Clone, Default)]
+pub struct ImportedModel {
+    pub meshes: Vec<MeshId>,
+    pub textures: Vec<TextureId>,
+    pub nodes: Vec<SceneNode>,
+    pub roots: Vec<usize>,
+}
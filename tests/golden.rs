@@ -0,0 +1,171 @@
+//! Renders reference scenes offscreen and compares them with the PNGs in
+//! `tests/golden`. They need a Vulkan device and are ignored by a plain `cargo test`,
+//! run them on lavapipe so the output matches across machines:
+//!
+//! ```text
+//! VK_ICD_FILENAMES=/usr/share/vulkan/icd.d/lvp_icd.x86_64.json cargo test --test golden -- --ignored
+//! ```
+//!
+//! `UPDATE_GOLDENS=1` overwrites the references with the current output. A failed
+//! comparison writes `<name>.actual.png` and `<name>.diff.png` next to the reference.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use image::{Rgba, RgbaImage};
+use learn_vk::{application::App, config::AppConfig, scene::MeshInstance, MyError};
+use nalgebra_glm as glm;
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+
+/// Largest difference of a channel that still counts as a match.
+const CHANNEL_TOLERANCE: u8 = 4;
+/// Fraction of pixels allowed to differ by more than the tolerance, rasterizers may
+/// disagree on a few edge pixels.
+const MAX_MISMATCHED: f32 = 0.001;
+
+/// Instances are created one at a time, some drivers don't like concurrent ones.
+static DEVICE_LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+#[ignore = "needs a Vulkan device, run with --ignored on lavapipe"]
+fn default_rectangle() {
+    check("default_rectangle", 1, |app| unsafe {
+        let rectangle = app.add_default_rectangle()?;
+
+        let scene = app.get_scene_mut();
+        scene.clear();
+        scene.add_instance(MeshInstance::new(rectangle, glm::identity()));
+
+        Ok(())
+    });
+}
+
+#[test]
+#[ignore = "needs a Vulkan device, run with --ignored on lavapipe"]
+fn viking_room() {
+    check("viking_room", 1, |_| Ok(()));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device, run with --ignored on lavapipe"]
+fn viking_room_msaa() {
+    check("viking_room_msaa", 4, |_| Ok(()));
+}
+
+/// Renders the default scene after `setup` changed it and compares the frame with
+/// the golden image `name`.
+fn check<F>(name: &str, msaa_samples: u32, setup: F)
+where
+    F: FnOnce(&mut App) -> Result<(), MyError>,
+{
+    let actual = render(msaa_samples, setup)
+        .unwrap_or_else(|e| panic!("Failed to render {}: {}", name, e));
+
+    let golden = get_golden_dir().join(format!("{}.png", name));
+
+    if env::var_os("UPDATE_GOLDENS").is_some() {
+        save(&actual, &golden);
+        return;
+    }
+
+    let actual_path = golden.with_extension("actual.png");
+
+    let Ok(expected) = image::open(&golden) else {
+        save(&actual, &actual_path);
+        panic!(
+            "Missing golden image {}, the output was written to {}. Rerun with UPDATE_GOLDENS=1 to accept it.",
+            golden.display(),
+            actual_path.display()
+        );
+    };
+    let expected = expected.to_rgba8();
+
+    if expected.dimensions() != actual.dimensions() {
+        save(&actual, &actual_path);
+        panic!(
+            "{} is {:?} but the output is {:?}, written to {}",
+            golden.display(),
+            expected.dimensions(),
+            actual.dimensions(),
+            actual_path.display()
+        );
+    }
+
+    let (diff, mismatched) = compare(&expected, &actual);
+    let fraction = mismatched as f32 / (WIDTH * HEIGHT) as f32;
+
+    if fraction > MAX_MISMATCHED {
+        let diff_path = golden.with_extension("diff.png");
+        save(&actual, &actual_path);
+        save(&diff, &diff_path);
+
+        panic!(
+            "{} pixels ({:.3}%) differ from {}, see {} and {}",
+            mismatched,
+            fraction * 100.0,
+            golden.display(),
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn render<F>(msaa_samples: u32, setup: F) -> Result<RgbaImage, MyError>
+where
+    F: FnOnce(&mut App) -> Result<(), MyError>,
+{
+    let _lock = DEVICE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let config = AppConfig::new()
+        .with_msaa_samples(msaa_samples)
+        .with_shader_hot_reload(false);
+
+    unsafe {
+        let mut app = App::create_headless(WIDTH, HEIGHT, &config)?;
+
+        let result = setup(&mut app).and_then(|_| app.render_headless());
+        app.destroy();
+
+        result
+    }
+}
+
+/// Pixels differing by more than the tolerance are red in the diff image, the rest
+/// show the golden image faded to gray.
+fn compare(expected: &RgbaImage, actual: &RgbaImage) -> (RgbaImage, usize) {
+    let mut mismatched = 0;
+
+    let diff = RgbaImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let e = expected.get_pixel(x, y);
+        let a = actual.get_pixel(x, y);
+
+        if e.0.iter().zip(a.0).any(|(e, a)| e.abs_diff(a) > CHANNEL_TOLERANCE) {
+            mismatched += 1;
+            return Rgba([255, 0, 0, 255]);
+        }
+
+        let luma = (e[0] as u32 + e[1] as u32 + e[2] as u32) / 3;
+        let faded = (luma / 4 + 160) as u8;
+
+        Rgba([faded, faded, faded, 255])
+    });
+
+    (diff, mismatched)
+}
+
+fn get_golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn save(image: &RgbaImage, path: &Path) {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+
+    image.save(path).unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e));
+}